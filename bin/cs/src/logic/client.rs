@@ -1,17 +1,21 @@
-use failure::Error;
 use futures::Stream;
 use mu_proto::prelude::*;
 
-use logic::Handler;
+use super::Handler;
 
 impl<T> Handler<T>
 where
-    T: Stream<Item = NetworkEvent, Error = Error>,
+    T: Stream<Item = NetworkEvent> + Unpin + 'static,
 {
+    /// Clients don't send anything the CS handles yet, so every packet is reported.
+    pub fn client_dispatcher() -> Dispatcher<Self> {
+        Dispatcher::new()
+    }
+
     pub fn on_client_connected(&mut self, mut session: SessionRef) {
         let res = ConnectResult { res: 1 };
 
-        if session.send(res.to_packet()).is_err() {
+        if session.send_msg(&res).is_err() {
             session.close().ok();
             return;
         }
@@ -25,15 +29,18 @@ where
     }
 
     pub fn on_client_disconnected(&mut self, id: u32) {
-        println!("Client disconnected {}", id);
-        self.clients.remove(&id);
-    }
-
-    pub fn on_client_received(&mut self, session: SessionRef, pkt: MuPacket) {
-        match pkt.code {
-            0x01 => self.on_server_info(ServerInfo::parse(&pkt.data), session),
-            0x02 => (), //JoinServerStat
-            _ => println!("Unhandled packet: {}", pkt),
+        let session = match self.clients.remove(&id) {
+            Some(session) => session,
+            None => return println!("Client disconnected {}", id),
         };
+
+        let stats = session.stats();
+        println!(
+            "Client disconnected {} ({}), {} packets in, {} out",
+            id,
+            session.endpoint(),
+            stats.packets_in,
+            stats.packets_out
+        );
     }
 }
@@ -1,21 +1,46 @@
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
 use failure::Error;
 use futures::Stream;
 use mu_proto::prelude::*;
 
-use logic::Handler;
+use super::Handler;
+
+/// Game servers reporting over UDP which sent no status for this long are left out of the list.
+const STATUS_TIMEOUT: Duration = Duration::from_secs(15);
+/// How often game servers reporting over UDP are checked for a missing status.
+pub const STATUS_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
 
 pub struct GSInstance {
-    pub s_ref: SessionRef,
+    //Game servers which only report their status over UDP have no session.
+    pub s_ref: Option<SessionRef>,
     pub svr_code: u16,
-    pub ip: [u8; 16],
-    pub port: u16,
-    pub perc: u8,
     pub usr_cnt: u16,
-    pub acc_cnt: u16,
     pub mx_usr_cnt: u16,
+    pub last_seen: Instant,
 }
 
 impl GSInstance {
+    fn new(s_ref: Option<SessionRef>, msg: ServerInfo) -> GSInstance {
+        GSInstance {
+            s_ref,
+            svr_code: msg.svr_code,
+            usr_cnt: msg.usr_cnt,
+            mx_usr_cnt: msg.mx_usr_cnt,
+            last_seen: Instant::now(),
+        }
+    }
+
+    fn update(&mut self, msg: ServerInfo) {
+        self.usr_cnt = msg.usr_cnt;
+        self.last_seen = Instant::now();
+    }
+
+    fn is_stale(&self) -> bool {
+        self.s_ref.is_none() && self.last_seen.elapsed() >= STATUS_TIMEOUT
+    }
+
     pub fn load(&self) -> u8 {
         match self.mx_usr_cnt {
             0 => 0,
@@ -26,82 +51,110 @@ impl GSInstance {
 
 impl<T> Handler<T>
 where
-    T: Stream<Item = NetworkEvent, Error = Error>,
+    T: Stream<Item = NetworkEvent> + Unpin + 'static,
 {
-    pub fn on_server_info(&mut self, msg: ServerInfo, session: SessionRef) {
-        let id = session.id;
-        let mut opt = None;
+    pub fn gs_dispatcher() -> Dispatcher<Self> {
+        let mut dispatcher = Dispatcher::new();
 
-        match self.gs_map.get_mut(&id) {
-            None => {
-                let info = GSInstance {
-                    s_ref: session,
-                    svr_code: msg.svr_code,
-                    ip: msg.ip,
-                    port: msg.port,
-                    perc: msg.perc,
-                    usr_cnt: msg.usr_cnt,
-                    acc_cnt: msg.acc_cnt,
-                    mx_usr_cnt: msg.mx_usr_cnt,
-                };
-                opt = Some(info);
+        dispatcher
+            .register(Handler::on_server_info)
+            .register(Handler::on_join_server_stat);
+
+        dispatcher
+    }
+
+    pub fn on_server_info(&mut self, session: SessionRef, msg: ServerInfo) {
+        match self.gs_map.get_mut(&msg.svr_code) {
+            Some(info) if info.s_ref.as_ref().map(|s| s.id) == Some(session.id) => {
+                info.update(msg)
             }
-            Some(info) => {
-                info.perc = msg.perc;
-                info.usr_cnt = msg.usr_cnt;
-                info.acc_cnt = msg.acc_cnt;
+            //A game server reconnecting before its old link is noticed as lost takes its place.
+            _ => {
+                self.gs_map.insert(msg.svr_code, GSInstance::new(Some(session), msg));
             }
         };
 
-        if let Some(info) = opt {
-            self.gs_map.insert(id, info);
+        self.broadcast_server_list_upd();
+    }
+
+    /// Status datagrams are the only thing game servers send over UDP.
+    pub fn on_datagram(&mut self, socket: UdpSocketRef, from: SocketAddr, pkt: MuPacket) {
+        match Message::decode(&pkt, socket.profile) {
+            Ok(Message::ServerInfo(msg)) => self.on_server_status(from, msg),
+            Ok(_) => println!("Unexpected datagram from {}: {}", from, pkt),
+            Err(e) => println!("Invalid datagram from {}: {}", from, e),
         }
+    }
+
+    /// Keeps game servers reporting over UDP listed until they stop sending their status. They
+    /// are known by their code, since a restarted game server reports from another port.
+    fn on_server_status(&mut self, from: SocketAddr, msg: ServerInfo) {
+        match self.gs_status.get_mut(&msg.svr_code) {
+            None => {
+                println!("Game server {} reporting from {}.", msg.svr_code, from);
+                self.gs_status.insert(msg.svr_code, GSInstance::new(None, msg));
+            }
+            Some(info) => info.update(msg),
+        };
 
         self.broadcast_server_list_upd();
     }
 
-    pub fn broadcast_server_list_upd(&mut self) {
-        if let Some(pkt) = self.new_server_list_pkt() {
-            self.broadcast(pkt);
+    /// Drops game servers which stopped reporting their status, telling clients they are gone.
+    pub fn sweep_server_status(&mut self) {
+        let count = self.gs_status.len();
+        self.gs_status.retain(|code, info| {
+            if info.is_stale() {
+                println!("Game server {} stopped reporting its status.", code);
+            }
+            !info.is_stale()
+        });
+
+        if self.gs_status.len() != count {
+            self.broadcast_server_list_upd();
         }
     }
 
-    pub fn new_server_list_pkt(&self) -> Option<MuPacket> {
-        let mut list = ServerList::new(self.gs_map.len() as u16);
+    pub fn broadcast_server_list_upd(&mut self) {
+        let list = self.new_server_list();
+        self.broadcast(&list);
+    }
+
+    pub fn new_server_list(&self) -> ServerList {
+        let mut list = ServerList::new();
 
-        let mut cnt = 0;
-        for (_, info) in self.gs_map.iter() {
-            list.add(info.svr_code, info.load());
-            cnt += 1;
-        }
+        //A game server reporting both over its link and over UDP is listed by its link.
+        let status = self
+            .gs_status
+            .values()
+            .filter(|info| !self.gs_map.contains_key(&info.svr_code));
 
-        list.cnt = cnt;
+        for info in self.gs_map.values().chain(status) {
+            if !info.is_stale() {
+                list.add(info.svr_code, info.load());
+            }
+        }
 
-        Some(list.to_packet())
+        list
     }
 
     pub fn send_server_list(&mut self, session: &mut SessionRef) -> Result<(), Error> {
-        if let Some(pkt) = self.new_server_list_pkt() {
-            session.send(pkt)?
-        }
+        let list = self.new_server_list();
+        session.send_msg(&list)?;
 
         Ok(())
     }
 
     pub fn on_server_disconnected(&mut self, id: u32) {
-        self.gs_map.remove(&id);
+        self.gs_map.retain(|_, info| info.s_ref.as_ref().map(|s| s.id) != Some(id));
         self.broadcast_server_list_upd();
     }
 
-    pub fn on_server_connected(&mut self, session: SessionRef) {
+    pub fn on_server_connected(&mut self, _session: SessionRef) {
         //
     }
 
-    pub fn on_server_received(&mut self, session: SessionRef, pkt: MuPacket) {
-        match pkt.code {
-            0x01 => self.on_server_info(ServerInfo::parse(&pkt.data), session),
-            0x02 => (), //JoinServerStat
-            _ => println!("Unhandled packet: {}", pkt),
-        };
+    pub fn on_join_server_stat(&mut self, _session: SessionRef, _msg: JoinServerStat) {
+        //
     }
 }
@@ -2,45 +2,87 @@ mod gs;
 mod client;
 
 use std::collections::HashMap;
+use std::rc::Rc;
 use mu_proto::prelude::*;
-use failure::Error;
-use futures::{Async, Future, Poll, Stream};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use futures::{Stream, StreamExt};
+use tokio::time::{self, Interval, MissedTickBehavior};
 
 use super::consts;
-use self::gs::GSInstance;
+use self::gs::{GSInstance, STATUS_SWEEP_INTERVAL};
 
 pub struct Handler<T: Stream> {
-    gs_map: HashMap<u32, GSInstance>,
+    //Game servers linked to the connect server, by server code.
+    gs_map: HashMap<u16, GSInstance>,
+    //Game servers reporting their status over UDP, by server code.
+    gs_status: HashMap<u16, GSInstance>,
+    //Drops game servers that stopped reporting their status.
+    status_sweep: Interval,
     clients: HashMap<u32, SessionRef>,
+    gs_dispatcher: Rc<Dispatcher<Handler<T>>>,
+    client_dispatcher: Rc<Dispatcher<Handler<T>>>,
     io: T,
 }
 
 impl<T> Handler<T>
 where
-    T: Stream<Item = NetworkEvent, Error = Error>,
+    T: Stream<Item = NetworkEvent> + Unpin + 'static,
 {
     pub fn new(t: T) -> Handler<T> {
+        let mut status_sweep = time::interval(STATUS_SWEEP_INTERVAL);
+        status_sweep.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
         Handler {
             gs_map: HashMap::new(),
+            gs_status: HashMap::new(),
+            status_sweep,
             clients: HashMap::new(),
+            gs_dispatcher: Rc::new(Handler::gs_dispatcher()),
+            client_dispatcher: Rc::new(Handler::client_dispatcher()),
             io: t,
         }
     }
 
-    fn handle_net_event(&mut self, evt: NetworkEvent) -> Poll<(), Error> {
+    fn handle_net_event(&mut self, evt: NetworkEvent) {
         match evt {
             NetworkEvent::ClientConnected(session) => self.on_connected(session),
-            NetworkEvent::ClientDisconnected((id, kind)) => self.on_disconnected(id, kind),
+            NetworkEvent::ClientDisconnected((id, kind, reason)) => {
+                self.on_disconnected(id, kind, reason)
+            }
             NetworkEvent::ClientPacket((session, pkt)) => self.on_packet_received(session, pkt),
-        };
+            NetworkEvent::Datagram((socket, from, pkt)) => self.on_datagram(socket, from, pkt),
+            NetworkEvent::ShuttingDown => self.on_shutdown(),
+            //The connect server only listens, so it has no links to lose.
+            NetworkEvent::LinkLost(_)
+            | NetworkEvent::Reconnecting(_)
+            | NetworkEvent::LinkRestored(_)
+            | NetworkEvent::LinkFailed(_) => (),
+        }
+    }
 
-        Ok(Async::Ready(()))
+    fn on_shutdown(&mut self) {
+        println!(
+            "Shutting down with {} clients and {} game servers connected.",
+            self.clients.len(),
+            self.gs_map.len()
+        );
     }
 
-    fn on_packet_received(&mut self, session: SessionRef, pkt: MuPacket) {
-        match session.kind {
-            consts::GS_CONN => self.on_server_received(session, pkt),
-            _ => self.on_client_received(session, pkt),
+    fn on_packet_received(&mut self, mut session: SessionRef, pkt: MuPacket) {
+        let dispatcher = match session.kind {
+            consts::GS_CONN => Rc::clone(&self.gs_dispatcher),
+            _ => Rc::clone(&self.client_dispatcher),
+        };
+
+        match dispatcher.dispatch(self, session.clone(), &pkt) {
+            Ok(()) => (),
+            Err(DispatchError::Invalid(msg, e)) => {
+                println!("Invalid {:?} packet from session {}: {}", msg, session.id, e);
+                session.close().ok();
+            }
+            Err(e) => println!("{}: {}", e, pkt),
         }
     }
 
@@ -51,16 +93,39 @@ where
         }
     }
 
-    fn on_disconnected(&mut self, id: u32, kind: u8) {
+    fn on_disconnected(&mut self, id: u32, kind: u8, reason: DisconnectReason) {
+        //A game server that stops answering heartbeats is likely hung, not just gone.
+        if kind == consts::GS_CONN && reason == DisconnectReason::Idle {
+            println!("Game server session {} stopped responding.", id);
+        }
+
         match kind {
             consts::GS_CONN => self.on_server_disconnected(id),
             _ => self.on_client_disconnected(id),
         }
     }
 
-    fn broadcast(&mut self, pkt: MuPacket) {
-        for (_, session) in &mut self.clients {
-            if session.send(pkt.clone()).is_err() {
+    /// Sends the message to every client, serializing it once per client version.
+    fn broadcast<P: Protocol>(&mut self, msg: &P) {
+        let mut pkts: HashMap<ProtoProfile, Option<MuPacket>> = HashMap::new();
+
+        for session in self.clients.values_mut() {
+            let profile = session.profile;
+            let pkt = pkts.entry(profile).or_insert_with(|| match msg.to_packet(profile) {
+                Ok(pkt) => Some(pkt),
+                Err(err) => {
+                    println!("Failed to build broadcast for {:?}: {}", profile, err);
+                    None
+                }
+            });
+
+            //Clients of a version the message can't be built for are skipped.
+            let pkt = match pkt {
+                Some(pkt) => pkt.clone(),
+                None => continue,
+            };
+
+            if session.send(pkt).is_err() {
                 session.close().ok();
             }
         }
@@ -69,17 +134,20 @@ where
 
 impl<T> Future for Handler<T>
 where
-    T: Stream<Item = NetworkEvent, Error = Error>,
+    T: Stream<Item = NetworkEvent> + Unpin + 'static,
 {
-    type Item = ();
-    type Error = Error;
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        while self.status_sweep.poll_tick(cx).is_ready() {
+            self.sweep_server_status();
+        }
 
-    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         loop {
-            match self.io.poll()? {
-                Async::Ready(Some(evt)) => try_ready!(self.handle_net_event(evt)),
-                Async::Ready(None) => break Ok(Async::Ready(())),
-                Async::NotReady => break Ok(Async::NotReady),
+            match self.io.poll_next_unpin(cx) {
+                Poll::Ready(Some(evt)) => self.handle_net_event(evt),
+                Poll::Ready(None) => break Poll::Ready(()),
+                Poll::Pending => break Poll::Pending,
             }
         }
     }
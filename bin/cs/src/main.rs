@@ -1,64 +1,132 @@
 extern crate mu_proto;
 extern crate config;
-extern crate tokio_core;
-
-#[macro_use]
+extern crate tokio;
+extern crate util;
 extern crate futures;
-
 extern crate failure;
 
-use tokio_core::reactor::{Core, Handle};
+use std::time::Duration;
+
+use tokio::runtime::Runtime;
 use mu_proto::prelude::*;
+use util::settings::{self, Filters};
 
 mod logic;
 mod consts;
 
+const CONFIG_FILE: &str = "config/cs.toml";
+
 fn main() {
     println!("Starting Connect Server...");
 
-    let mut settings = config::Config::default();
-    settings
-        .merge(config::File::with_name("config/cs.toml"))
-        .expect("Failed to load config file.");
+    let settings = settings::load(CONFIG_FILE).expect("Failed to load config file.");
+
+    let runtime = Runtime::new().expect("Failed to start the runtime.");
+    let _guard = runtime.enter();
+    let capture = settings::setup_capture(&settings);
+    let svr = setup_networking(&settings, capture.clone());
+    svr.shutdown_handle().on_signals();
 
-    let mut reactor = Core::new().unwrap();
-    let svr = setup_networking(&settings, reactor.handle());
+    runtime.block_on(logic::Handler::new(svr));
 
-    reactor.run(logic::Handler::new(svr)).unwrap();
+    //Records still waiting on the capture writer would be lost on exit.
+    if let Some(capture) = capture {
+        capture.flush().ok();
+    }
 }
 
-fn setup_networking(settings: &config::Config, handle: Handle) -> Server {
-    let mut server = Server::new(handle);
+fn setup_networking(settings: &config::Config, capture: Option<Capture>) -> Server {
+    let mut server = Server::new();
+
+    if let Ok(ms) = settings.get_int("network.drain_ms") {
+        server.set_drain_timeout(Duration::from_millis(ms as u64));
+    }
+    let mut filters = Filters::new(CONFIG_FILE);
 
     //Setup external TCP Server
     {
-        let external_addr = match settings.get_str("network.external_addr") {
-            Ok(addr) => addr,
-            Err(_) => format!("0.0.0.0"),
-        };
-
-        let external_port = match settings.get_int("network.external_port") {
-            Ok(port) => port,
-            Err(_) => 44405,
+        let external_addr = settings
+            .get_string("network.external_addr")
+            .unwrap_or_else(|_| "0.0.0.0".to_owned());
+
+        let external_port = settings.get_int("network.external_port").unwrap_or(44405);
+
+        let mut opts = SessionOptions {
+            xor32: settings.get_bool("network.external_xor32").unwrap_or(true),
+            profile: settings::setup_profile(settings, "external"),
+            capture: capture.clone(),
+            filter: filters.setup(settings, "external"),
+            ..SessionOptions::default()
         };
+        settings::setup_session(settings, "external", &mut opts);
 
-        server.start_tcp(&external_addr, external_port as u16, consts::CLIENT_CONN).ok();
+        server.start_tcp(&external_addr, external_port as u16, consts::CLIENT_CONN, opts).ok();
     }
 
     //Setup internal TCP Server
     {
-        let internal_addr = match settings.get_str("network.internal_addr") {
-            Ok(addr) => addr,
-            Err(_) => format!("0.0.0.0"),
+        let internal_addr = settings
+            .get_string("network.internal_addr")
+            .unwrap_or_else(|_| "0.0.0.0".to_owned());
+
+        let internal_port = settings.get_int("network.internal_port").unwrap_or(55557);
+
+        //Game servers on this host may connect through a Unix socket instead.
+        let internal_path = settings.get_string("network.internal_path").ok();
+
+        let mut opts = SessionOptions {
+            capture: capture.clone(),
+            filter: filters.setup(settings, "internal"),
+            ..SessionOptions::default()
         };
+        settings::setup_session(settings, "internal", &mut opts);
+
+        //Listening in plain text instead would expose the links the config meant to protect.
+        match settings::setup_tls(settings, "internal") {
+            Ok(tls) => {
+                opts.tls = tls;
+
+                match internal_path {
+                    Some(path) => start_unix(&mut server, &path, consts::GS_CONN, opts),
+                    None => {
+                        let port = internal_port as u16;
+                        server.start_tcp(&internal_addr, port, consts::GS_CONN, opts).ok();
+                    }
+                }
+            }
+            Err(e) => println!("Not listening for game servers, invalid TLS setup: {}", e),
+        }
+    }
 
-        let internal_port = match settings.get_int("network.internal_port") {
-            Ok(port) => port,
-            Err(_) => 55557,
+    //Setup UDP socket for game server status, only when configured as datagrams are not
+    //authenticated like the internal links.
+    if let Ok(status_port) = settings.get_int("network.status_port") {
+        let status_addr = settings
+            .get_string("network.status_addr")
+            .unwrap_or_else(|_| "127.0.0.1".to_owned());
+
+        let opts = SessionOptions {
+            capture: capture.clone(),
+            filter: filters.setup(settings, "status"),
+            ..SessionOptions::default()
         };
 
-        server.start_tcp(&internal_addr, internal_port as u16, consts::GS_CONN).ok();
+        server.start_udp(&status_addr, status_port as u16, consts::GS_CONN, opts).ok();
     }
 
+    filters.watch();
+
     server
 }
+
+#[cfg(unix)]
+fn start_unix(server: &mut Server, path: &str, kind: u8, opts: SessionOptions) {
+    if let Err(e) = server.start_unix(path, kind, opts) {
+        println!("Failed to listen on {}: {}", path, e);
+    }
+}
+
+#[cfg(not(unix))]
+fn start_unix(_server: &mut Server, path: &str, _kind: u8, _opts: SessionOptions) {
+    println!("Not listening on {}, Unix sockets aren't supported here.", path);
+}
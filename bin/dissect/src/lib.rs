@@ -0,0 +1,169 @@
+//! Dissection of MU packets, shared by the `mu-dissect` binary and its tests.
+
+extern crate failure;
+#[macro_use]
+extern crate failure_derive;
+extern crate mu_proto;
+
+use std::io::{self, Write};
+
+use failure::Error;
+use mu_proto::{xor32, FrameDecoder, Message, MuPacket, ProtoProfile, SimpleModulus};
+
+#[derive(Debug, Fail)]
+pub enum DissectError {
+    #[fail(display = "Invalid hex: {}", _0)]
+    InvalidHex(String),
+}
+
+/// Reads hex bytes, ignoring separators, `0x` prefixes and comments.
+pub fn parse_hex(text: &str) -> Result<Vec<u8>, DissectError> {
+    let mut bytes = vec![];
+
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or("");
+
+        for token in line.split(|c: char| c.is_whitespace() || c == ',' || c == ':') {
+            let token = token.trim_start_matches("0x").trim_start_matches("0X");
+
+            if token.len() % 2 != 0 {
+                return Err(DissectError::InvalidHex(token.to_owned()));
+            }
+
+            for i in (0..token.len()).step_by(2) {
+                let b = u8::from_str_radix(&token[i..i + 2], 16)
+                    .map_err(|_| DissectError::InvalidHex(token.to_owned()))?;
+                bytes.push(b);
+            }
+        }
+    }
+
+    Ok(bytes)
+}
+
+pub struct Dissector {
+    pub profile: ProtoProfile,
+    pub xor32: bool,
+    pub cipher: Option<SimpleModulus>,
+    pub color: bool,
+}
+
+impl Dissector {
+    /// Cuts raw bytes into frames and dissects each one of them.
+    pub fn stream<W: Write>(&self, out: &mut W, bytes: &[u8]) -> io::Result<()> {
+        let mut frames = FrameDecoder::new();
+        frames.feed(bytes);
+
+        loop {
+            match frames.next_frame() {
+                Ok(Some(frame)) => {
+                    let frame = frame.to_vec();
+
+                    match self.open(out, frame) {
+                        Ok(Some(frame)) => self.packet(out, &frame)?,
+                        Ok(None) => (),
+                        Err(e) => writeln!(out, "Failed to open frame: {}", e)?,
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    writeln!(out, "Framing stopped: {}", e)?;
+                    break;
+                }
+            }
+        }
+
+        //Whatever is left couldn't be framed, either a bad header or a truncated frame.
+        let left = frames.pending();
+        if left > 0 {
+            writeln!(out, "{} trailing bytes not framed:", left)?;
+            writeln!(out, "  {}", self.unknown(&bytes[bytes.len() - left..]))?;
+        }
+
+        Ok(())
+    }
+
+    /// Reverts SimpleModulus and XOR32, the same way a listener does. C3/C4 frames can't be
+    /// opened without keys, so they are shown as they are and `None` is returned.
+    fn open<W: Write>(&self, out: &mut W, mut frame: Vec<u8>) -> Result<Option<Vec<u8>>, Error> {
+        if frame[0] == 0xC3 || frame[0] == 0xC4 {
+            let cipher = match self.cipher {
+                Some(ref cipher) => cipher,
+                None => {
+                    self.sealed(out, &frame)?;
+                    return Ok(None);
+                }
+            };
+
+            let (plain, serial) = cipher.decrypt_frame(&frame)?;
+            writeln!(out, "Encrypted frame, serial {:02X}", serial)?;
+            frame = plain;
+        }
+
+        if self.xor32 {
+            xor32::decode(&mut frame);
+        }
+
+        Ok(Some(frame))
+    }
+
+    //The code is encrypted too, so nothing past the size is known.
+    fn sealed<W: Write>(&self, out: &mut W, frame: &[u8]) -> io::Result<()> {
+        let body = if frame[0] == 0xC3 { &frame[2..] } else { &frame[3..] };
+
+        writeln!(out, "{}", hex(frame))?;
+        writeln!(out, "  encrypted, keys required")?;
+        writeln!(out, "  {}", self.unknown(body))?;
+        writeln!(out)
+    }
+
+    pub fn packet<W: Write>(&self, out: &mut W, frame: &[u8]) -> io::Result<()> {
+        writeln!(out, "{}", hex(frame))?;
+
+        let pkt = match MuPacket::new(frame) {
+            Ok(pkt) => pkt,
+            Err(e) => {
+                writeln!(out, "  {}", e)?;
+                writeln!(out, "  {}", self.unknown(frame))?;
+                return writeln!(out);
+            }
+        };
+
+        let body = pkt.data();
+
+        match Message::decode(&pkt, self.profile) {
+            Ok(msg) => {
+                writeln!(out, "{:?} ({})", msg.msg(), self.profile)?;
+                writeln!(out, "{:#?}", msg)?;
+
+                let used = msg.size(self.profile);
+                if body.len() > used {
+                    writeln!(out, "  {} unknown bytes after the message:", body.len() - used)?;
+                    writeln!(out, "  {}", self.unknown(&body[used..]))?;
+                }
+            }
+            Err(e) => {
+                writeln!(out, "  {} ({})", e, self.profile)?;
+                writeln!(out, "  {}", self.unknown(body))?;
+            }
+        }
+
+        writeln!(out)
+    }
+
+    /// Hex of bytes no message accounts for, highlighted in red on terminals.
+    fn unknown(&self, bytes: &[u8]) -> String {
+        if self.color {
+            format!("\x1b[31m{}\x1b[0m", hex(bytes))
+        } else {
+            format!("[{}]", hex(bytes))
+        }
+    }
+}
+
+fn hex(buf: &[u8]) -> String {
+    buf.iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,137 @@
+//! Decodes hex dumps of MU packets into their protocol structs.
+//!
+//! Usage: mu-dissect [--file PATH | --capture PATH] [--profile NAME] [--xor32]
+//!        [--keys ENC DEC] [--no-color]
+//!
+//! Hex is read from stdin when no file is given. Separators, `0x` prefixes and `#` comments
+//! are ignored, so dumps can be pasted straight from logs.
+
+extern crate failure;
+extern crate mu_dissect;
+extern crate mu_proto;
+
+use std::env;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::process;
+
+use failure::Error;
+use mu_dissect::{parse_hex, Dissector};
+use mu_proto::{CaptureReader, ProtoProfile, SimpleModulus};
+
+enum Input {
+    Stdin,
+    File(String),
+    Capture(String),
+}
+
+struct Options {
+    input: Input,
+    profile: ProtoProfile,
+    xor32: bool,
+    keys: Option<(String, String)>,
+    color: bool,
+}
+
+fn usage() -> ! {
+    println!("Usage: mu-dissect [options]");
+    println!();
+    println!("Options:");
+    println!("  --file PATH     Reads hex from the file instead of stdin.");
+    println!("  --capture PATH  Dissects every packet of a capture file.");
+    println!("  --profile NAME  Client version: 0.97d, 1.04d or s6. Defaults to s6.");
+    println!("  --xor32         Hex was taken off the wire of a XOR32 listener.");
+    println!("  --keys ENC DEC  Server SimpleModulus keys, to open C3/C4 packets.");
+    println!("  --no-color      Doesn't highlight unknown bytes with colors.");
+    process::exit(2)
+}
+
+fn parse_args() -> Options {
+    let mut args = env::args().skip(1);
+    let mut opts = Options {
+        input: Input::Stdin,
+        profile: ProtoProfile::default(),
+        xor32: false,
+        keys: None,
+        color: true,
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--file" => opts.input = Input::File(args.next().unwrap_or_else(|| usage())),
+            "--capture" => opts.input = Input::Capture(args.next().unwrap_or_else(|| usage())),
+            "--profile" => {
+                let name = args.next().unwrap_or_else(|| usage());
+                opts.profile = name.parse().unwrap_or_else(|e| {
+                    println!("{}", e);
+                    process::exit(2)
+                });
+            }
+            "--xor32" => opts.xor32 = true,
+            "--keys" => match (args.next(), args.next()) {
+                (Some(enc), Some(dec)) => opts.keys = Some((enc, dec)),
+                _ => usage(),
+            },
+            "--no-color" => opts.color = false,
+            _ => usage(),
+        }
+    }
+
+    opts
+}
+
+fn main() {
+    let opts = parse_args();
+
+    if let Err(e) = run(&opts) {
+        println!("{}", e);
+        process::exit(1);
+    }
+}
+
+fn run(opts: &Options) -> Result<(), Error> {
+    let cipher = match opts.keys {
+        Some((ref enc, ref dec)) => Some(SimpleModulus::load(enc, dec)?),
+        None => None,
+    };
+
+    let dissector = Dissector {
+        profile: opts.profile,
+        xor32: opts.xor32,
+        cipher,
+        color: opts.color,
+    };
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    let text = match opts.input {
+        Input::Capture(ref path) => {
+            //Captured frames are already plain, so they skip XOR32 and SimpleModulus.
+            for rec in CaptureReader::open(path)? {
+                let rec = rec?;
+                writeln!(
+                    out,
+                    "[{}] session {} kind {} {}",
+                    rec.time, rec.session, rec.kind, rec.direction
+                )?;
+                dissector.packet(&mut out, &rec.frame)?;
+            }
+            return Ok(());
+        }
+        Input::File(ref path) => {
+            let mut text = String::new();
+            File::open(path)?.read_to_string(&mut text)?;
+            text
+        }
+        Input::Stdin => {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text)?;
+            text
+        }
+    };
+
+    dissector.stream(&mut out, &parse_hex(&text)?)?;
+    Ok(())
+}
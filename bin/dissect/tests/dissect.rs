@@ -0,0 +1,65 @@
+//! Hex parsing and frame dissection of `mu-dissect`.
+
+extern crate mu_dissect;
+extern crate mu_proto;
+
+use mu_dissect::{parse_hex, DissectError, Dissector};
+use mu_proto::ProtoProfile;
+
+fn dissect(hex: &str) -> String {
+    let dissector = Dissector {
+        profile: ProtoProfile::Season6,
+        xor32: false,
+        cipher: None,
+        color: false,
+    };
+
+    let mut out = vec![];
+    dissector.stream(&mut out, &parse_hex(hex).unwrap()).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn parses_pasted_hex() {
+    let text = "C1 04 00 01 # hello\n0xC2,0x00:07 F4 06\n0000 # count\n";
+    assert_eq!(
+        parse_hex(text).unwrap(),
+        [0xC1, 0x04, 0x00, 0x01, 0xC2, 0x00, 0x07, 0xF4, 0x06, 0x00, 0x00]
+    );
+
+    assert!(parse_hex("").unwrap().is_empty());
+    assert_eq!(parse_hex("c1040001").unwrap(), [0xC1, 0x04, 0x00, 0x01]);
+}
+
+#[test]
+fn rejects_invalid_hex() {
+    match parse_hex("C1 0") {
+        Err(DissectError::InvalidHex(token)) => assert_eq!(token, "0"),
+        other => panic!("expected invalid hex, got {:?}", other),
+    }
+
+    assert!(parse_hex("C1 ZZ").is_err());
+}
+
+#[test]
+fn dissects_known_frames() {
+    let out = dissect("C2 00 0B F4 06 00 01 15 00 50 FF");
+
+    assert!(out.contains("ServerList (s6)"), "{}", out);
+    assert!(out.contains("idx: 21"), "{}", out);
+    assert!(out.contains("load: 80"), "{}", out);
+
+    //Bytes past the message are shown apart, as no field accounts for them.
+    let out = dissect("C1 06 00 01 AB CD");
+    assert!(out.contains("ConnectResult"), "{}", out);
+    assert!(out.contains("2 unknown bytes after the message:\n  [AB CD]"), "{}", out);
+}
+
+#[test]
+fn encrypted_frames_need_keys() {
+    let out = dissect("C3 0D DB CC 2C B0 8B 3A 40 3C 1C 35 0D");
+
+    assert!(out.contains("encrypted, keys required"), "{}", out);
+    assert!(out.contains("[DB CC 2C B0 8B 3A 40 3C 1C 35 0D]"), "{}", out);
+    assert!(!out.contains("ConnectResult") && !out.contains("Unknown"), "{}", out);
+}
@@ -1,56 +1,97 @@
+use std::rc::Rc;
 use mu_proto::prelude::*;
-use futures::{Async, Future, Poll, Stream};
-use failure::Error;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use futures::{Stream, StreamExt};
 
 pub struct Handler<T: Stream> {
+    dispatcher: Rc<Dispatcher<Handler<T>>>,
     io: T,
 }
 
 impl<T> Handler<T>
 where
-    T: Stream<Item = NetworkEvent, Error = Error>,
+    T: Stream<Item = NetworkEvent> + Unpin + 'static,
 {
     pub fn new(t: T) -> Handler<T> {
-        Handler { io: t }
+        Handler {
+            dispatcher: Rc::new(Dispatcher::new()),
+            io: t,
+        }
     }
 
-    pub fn handle_net_event(&self, evt: NetworkEvent) -> Poll<(), Error> {
+    pub fn handle_net_event(&mut self, evt: NetworkEvent) {
         match evt {
             NetworkEvent::ClientConnected(session) => self.on_connected(session),
-            NetworkEvent::ClientDisconnected((id, kind)) => self.on_disconnected(id, kind),
+            NetworkEvent::ClientDisconnected((id, kind, reason)) => {
+                self.on_disconnected(id, kind, reason)
+            }
             NetworkEvent::ClientPacket((session, pkt)) => self.on_received(session, pkt),
-        };
-        Ok(Async::Ready(()))
+            NetworkEvent::LinkLost((_, addr)) => {
+                println!("Lost the connect server at {}, reconnecting.", addr)
+            }
+            NetworkEvent::Reconnecting((_, attempt)) => {
+                println!("Reconnecting to the connect server, attempt {}.", attempt)
+            }
+            NetworkEvent::LinkRestored(session) => self.on_link_restored(session),
+            NetworkEvent::LinkFailed((_, attempts)) => {
+                println!("Gave up on the connect server after {} attempts.", attempts)
+            }
+            //The status socket only sends, the connect server doesn't answer it.
+            NetworkEvent::Datagram((_, from, pkt)) => {
+                println!("Unexpected datagram from {}: {}", from, pkt)
+            }
+            NetworkEvent::ShuttingDown => self.on_shutdown(),
+        }
     }
 
-    fn on_connected(&self, mut session: SessionRef) {
-        println!("Client connected {}", session.id)
+    /// The connect server may have restarted and forgotten us, so this is where registration
+    /// goes once the game server sends one.
+    fn on_link_restored(&mut self, session: SessionRef) {
+        println!("Connect server link restored on session {}.", session.id)
     }
 
-    fn on_disconnected(&self, id: u32, kind: u8) {
-        println!("Client disconnected {}", id)
+    /// Last chance to reach the sessions, the server drains and stops right after. The game
+    /// server holds no state of its own yet, so nothing is persisted here.
+    fn on_shutdown(&mut self) {
+        println!("Shutting down game server.");
     }
 
-    fn on_received(&self, session: SessionRef, pkt: MuPacket) {
-        match pkt.code {
-            _ => println!("Unhandled packet: {}", pkt),
-        };
+    fn on_connected(&self, session: SessionRef) {
+        println!("Client connected {} from {}", session.id, session.endpoint())
+    }
+
+    fn on_disconnected(&self, id: u32, _kind: u8, reason: DisconnectReason) {
+        println!("Client disconnected {} ({})", id, reason)
+    }
+
+    fn on_received(&mut self, mut session: SessionRef, pkt: MuPacket) {
+        let dispatcher = Rc::clone(&self.dispatcher);
+
+        match dispatcher.dispatch(self, session.clone(), &pkt) {
+            Ok(()) => (),
+            Err(DispatchError::Invalid(msg, e)) => {
+                println!("Invalid {:?} packet from session {}: {}", msg, session.id, e);
+                session.close().ok();
+            }
+            Err(e) => println!("{}: {}", e, pkt),
+        }
     }
 }
 
 impl<T> Future for Handler<T>
 where
-    T: Stream<Item = NetworkEvent, Error = Error>,
+    T: Stream<Item = NetworkEvent> + Unpin + 'static,
 {
-    type Item = ();
-    type Error = Error;
+    type Output = ();
 
-    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
         loop {
-            match self.io.poll()? {
-                Async::Ready(Some(evt)) => try_ready!(self.handle_net_event(evt)),
-                Async::Ready(None) => break Ok(Async::Ready(())),
-                Async::NotReady => break Ok(Async::NotReady),
+            match self.io.poll_next_unpin(cx) {
+                Poll::Ready(Some(evt)) => self.handle_net_event(evt),
+                Poll::Ready(None) => break Poll::Ready(()),
+                Poll::Pending => break Poll::Pending,
             }
         }
     }
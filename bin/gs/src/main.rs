@@ -1,101 +1,189 @@
 extern crate config;
 extern crate mu_proto;
-extern crate scheduled_executor;
-extern crate tokio_core;
+extern crate tokio;
+extern crate util;
 extern crate failure;
-
-#[macro_use]
 extern crate futures;
 
 mod consts;
 mod logic;
 
-// use scheduled_executor::CoreExecutor;
-use tokio_core::reactor::{Core, Handle};
+use tokio::runtime::Runtime;
 
-// use std::time::Duration;
+use std::net::SocketAddr;
+use std::time::Duration;
 
 use mu_proto::prelude::*;
+use util::settings::{self, Filters};
 
+const CONFIG_FILE: &str = "config/gs.toml";
 
 fn main() {
     println!("Starting Game Server...");
 
-    let mut settings = config::Config::default();
-    settings
-        .merge(config::File::with_name("config/gs.toml"))
-        .expect("Failed to load config file.");
+    let settings = settings::load(CONFIG_FILE).expect("Failed to load config file.");
+
+    let runtime = Runtime::new().expect("Failed to start the runtime.");
+    let _guard = runtime.enter();
+    let capture = settings::setup_capture(&settings);
+    let mut svr = setup_networking(&settings, capture.clone());
+    svr.shutdown_handle().on_signals();
+    setup_status(&mut svr, &settings);
 
-    let mut reactor = Core::new().unwrap();
-    let svr = setup_networking(&settings, reactor.handle());
-    // let _executor = setup_executor(&server, &settings);
+    runtime.block_on(logic::Handler::new(svr));
 
-    reactor.run(logic::Handler::new(svr)).unwrap();
+    //Records still waiting on the capture writer would be lost on exit.
+    if let Some(capture) = capture {
+        capture.flush().ok();
+    }
 }
 
-fn setup_networking(settings: &config::Config, handle: Handle) -> Server {
-    let mut server = Server::new(handle);
+fn setup_networking(settings: &config::Config, capture: Option<Capture>) -> Server {
+    let mut server = Server::new();
 
-    //Setup TCP Server
+    if let Ok(ms) = settings.get_int("network.drain_ms") {
+        server.set_drain_timeout(Duration::from_millis(ms as u64));
+    }
+    let mut filters = Filters::new(CONFIG_FILE);
+
+    //Setup SimpleModulus keys, used by clients on C3/C4 packets
     {
-        let listen_addr = match settings.get_str("network.listen_addr") {
-            Ok(addr) => addr,
-            Err(_) => format!("0.0.0.0"),
-        };
+        let enc_key = settings
+            .get_string("crypto.enc_key")
+            .unwrap_or_else(|_| "data/Enc1.dat".to_owned());
+
+        let dec_key = settings
+            .get_string("crypto.dec_key")
+            .unwrap_or_else(|_| "data/Dec2.dat".to_owned());
+
+        match SimpleModulus::load(&enc_key, &dec_key) {
+            Ok(cipher) => server.set_simple_modulus(cipher),
+            Err(e) => println!("Failed to load SimpleModulus keys: {}", e),
+        }
+    }
 
-        let listen_port = match settings.get_int("network.listen_port") {
-            Ok(port) => port,
-            Err(_) => 55590,
+    //Setup TCP Server
+    {
+        let listen_addr = settings
+            .get_string("network.listen_addr")
+            .unwrap_or_else(|_| "0.0.0.0".to_owned());
+
+        let listen_port = settings.get_int("network.listen_port").unwrap_or(55590);
+
+        let mut opts = SessionOptions {
+            xor32: settings.get_bool("network.listen_xor32").unwrap_or(true),
+            profile: settings::setup_profile(settings, "listen"),
+            capture,
+            filter: filters.setup(settings, "listen"),
+            ..SessionOptions::default()
         };
+        settings::setup_session(settings, "listen", &mut opts);
 
-        server.start_tcp(&listen_addr, listen_port as u16, consts::CLIENT_CONN).ok();
+        server.start_tcp(&listen_addr, listen_port as u16, consts::CLIENT_CONN, opts).ok();
     }
 
+    filters.watch();
+
     {
-        let addr = match settings.get_str("network.cs_addr") {
-            Ok(addr) => addr,
-            Err(_) => format!("127.0.0.1"),
-        };
+        let addr = settings
+            .get_string("network.cs_addr")
+            .unwrap_or_else(|_| "127.0.0.1".to_owned());
 
-        let port = match settings.get_int("network.cs_port") {
-            Ok(port) => port,
-            Err(_) => 55557,
+        let port = settings.get_int("network.cs_port").unwrap_or(55557);
+
+        //A connect server on this host may be reached through its Unix socket instead.
+        let main = match settings.get_string("network.cs_path") {
+            Ok(path) => format!("unix:{}", path),
+            Err(_) => format!("{}:{}", addr, port),
         };
 
-        server.connect_to(&addr, port as u16, consts::CS_CONN).ok();
+        let mut endpoints = vec![];
+
+        match main.parse() {
+            Ok(endpoint) => endpoints.push(endpoint),
+            Err(e) => println!("Invalid connect server address {}: {}", main, e),
+        }
+
+        //Standby connect servers, tried in order when the main one can't be reached.
+        for entry in settings.get::<Vec<String>>("network.cs_failover").unwrap_or_default() {
+            match entry.parse() {
+                Ok(endpoint) => endpoints.push(endpoint),
+                Err(e) => println!("Invalid failover address {}: {}", entry, e),
+            }
+        }
+
+        let mut opts = SessionOptions::default();
+        settings::setup_session(settings, "cs", &mut opts);
+        settings::setup_reconnect(settings, "cs", &mut opts.reconnect);
+
+        //Connecting in plain text instead would expose the link the config meant to protect.
+        let res = settings::setup_tls(settings, "cs").and_then(|tls| {
+            opts.tls = tls;
+            server.connect_to_any(endpoints, consts::CS_CONN, opts)
+        });
+
+        if let Err(e) = res {
+            println!("Failed to connect to the connect server: {}", e);
+        }
     }
 
     server
 }
 
-// fn setup_executor(server: &Server, settings: &config::Config) -> CoreExecutor {
-//     let udp_client = match server.get_udp_client(consts::CS_SERVER) {
-//             Err(why) => panic!("Failed to get udp client: {:?}", why),
-//             Ok(client) => client,
-//         };
-
-//         let max_user = match settings.get_int("general.max_user") {
-//             Ok(max) => max,
-//             Err(_) => 100,
-//         };
-//         let server_code = match settings.get_int("general.server_code") {
-//             Ok(code) => code,
-//             Err(_) => 1,
-//         };
-
-//         let executor = CoreExecutor::new().expect("Failed to setup Scheduled Executor");
-//         executor.schedule_fixed_rate(Duration::from_secs(2), Duration::from_secs(5), move |_| {
-//             println!("Sending keep alive!");
-//             let pkt = ServerInfo {
-//                 svr_code: server_code as u16,
-//                 perc: 0,
-//                 usr_cnt: 0,
-//                 acc_cnt: 0,
-//                 pcbng_cnt: 0,
-//                 mx_usr_cnt: max_user as u16,
-//             };
-//             udp_client.send(&pkt.to_packet()).ok();
-//         });
-        
-//         executor
-// }
\ No newline at end of file
+/// Reports this game server to the connect server in a `ServerInfo` datagram every
+/// `network.status_ms`, as long as `network.cs_status_port` is set.
+fn setup_status(server: &mut Server, settings: &config::Config) {
+    let port = match settings.get_int("network.cs_status_port") {
+        Ok(port) => port,
+        Err(_) => return,
+    };
+
+    let addr = settings.get_string("network.cs_addr").unwrap_or_else(|_| "127.0.0.1".to_owned());
+
+    let cs: SocketAddr = match format!("{}:{}", addr, port).parse() {
+        Ok(cs) => cs,
+        Err(e) => return println!("Invalid connect server status address {}: {}", addr, e),
+    };
+
+    let period = match settings.get_int("network.status_ms") {
+        Ok(ms) => Duration::from_millis(ms as u64),
+        Err(_) => Duration::from_secs(5),
+    };
+
+    //Only sends, so any free port will do.
+    let local = if cs.is_ipv6() { "[::]" } else { "0.0.0.0" };
+    let socket = match server.start_udp(local, 0, consts::CS_CONN, SessionOptions::default()) {
+        Ok(socket) => socket,
+        Err(e) => return println!("Failed to bind the status socket: {}", e),
+    };
+
+    let mut ip = [0u8; 16];
+    let public_addr = settings.get_string("network.public_addr").unwrap_or_default();
+    for (dst, src) in ip.iter_mut().zip(public_addr.bytes()) {
+        *dst = src;
+    }
+
+    let info = ServerInfo {
+        svr_code: settings.get_int("general.server_code").unwrap_or(1) as u16,
+        ip,
+        port: settings.get_int("network.listen_port").unwrap_or(55590) as u16,
+        perc: 0,
+        usr_cnt: 0,
+        acc_cnt: 0,
+        mx_usr_cnt: settings.get_int("general.max_user").unwrap_or(100) as u16,
+    };
+
+    let shutdown = server.shutdown_handle();
+
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(period);
+
+        while !shutdown.is_shutting_down() {
+            ticks.tick().await;
+
+            if let Err(e) = socket.send_msg_to(&info, cs) {
+                println!("Failed to send status to the connect server: {}", e);
+            }
+        }
+    });
+}
@@ -0,0 +1,274 @@
+//! Replays a session recorded by a CS or GS capture against a running server, sending what the
+//! client sent and comparing every server response with the recorded one.
+//!
+//! Usage: mu-replay <capture> <addr:port> [--session ID] [--xor32] [--keys ENC DEC]
+//!        [--timeout MS]
+
+extern crate failure;
+#[macro_use]
+extern crate failure_derive;
+extern crate mu_proto;
+
+use std::env;
+use std::io;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::process;
+use std::time::Duration;
+
+use failure::Error;
+use mu_proto::{xor32, CaptureDirection, CaptureReader, CaptureRecord, FrameDecoder, SimpleModulus};
+
+#[derive(Debug, Fail)]
+enum ReplayError {
+    #[fail(display = "No packets of session {} on the capture", _0)]
+    SessionNotFound(u32),
+    #[fail(display = "Capture has no packets")]
+    EmptyCapture,
+    #[fail(display = "Encrypted packet on the capture, but no --keys were given")]
+    MissingKeys,
+    #[fail(display = "Server closed the connection")]
+    Closed,
+}
+
+struct Options {
+    capture: String,
+    addr: String,
+    session: Option<u32>,
+    xor32: bool,
+    keys: Option<(String, String)>,
+    timeout: u64,
+}
+
+fn usage() -> ! {
+    println!("Usage: mu-replay <capture> <addr:port> [options]");
+    println!();
+    println!("Options:");
+    println!("  --session ID    Session to replay. The first one on the capture if omitted.");
+    println!("  --xor32         Server listener uses XOR32, like retail client listeners.");
+    println!("  --keys ENC DEC  Client SimpleModulus keys, needed by C3/C4 packets.");
+    println!("  --timeout MS    How long to wait for each response. Defaults to 2000.");
+    process::exit(2)
+}
+
+fn parse_args() -> Options {
+    let mut args = env::args().skip(1);
+    let mut positional = vec![];
+    let mut opts = Options {
+        capture: String::new(),
+        addr: String::new(),
+        session: None,
+        xor32: false,
+        keys: None,
+        timeout: 2000,
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--session" => {
+                let id = args.next().and_then(|s| s.parse().ok());
+                opts.session = Some(id.unwrap_or_else(|| usage()));
+            }
+            "--xor32" => opts.xor32 = true,
+            "--keys" => match (args.next(), args.next()) {
+                (Some(enc), Some(dec)) => opts.keys = Some((enc, dec)),
+                _ => usage(),
+            },
+            "--timeout" => {
+                let ms = args.next().and_then(|s| s.parse().ok());
+                opts.timeout = ms.unwrap_or_else(|| usage());
+            }
+            "-h" | "--help" => usage(),
+            _ => positional.push(arg),
+        }
+    }
+
+    if positional.len() != 2 {
+        usage();
+    }
+
+    opts.addr = positional.pop().unwrap();
+    opts.capture = positional.pop().unwrap();
+    opts
+}
+
+fn main() {
+    let opts = parse_args();
+
+    match replay(&opts) {
+        Ok(0) => println!("Replay finished, every response matched."),
+        Ok(n) => {
+            println!("Replay finished with {} mismatches.", n);
+            process::exit(1);
+        }
+        Err(e) => {
+            println!("Replay failed: {}", e);
+            process::exit(2);
+        }
+    }
+}
+
+/// Loads the records of the session to replay.
+fn load_session(opts: &Options) -> Result<Vec<CaptureRecord>, Error> {
+    let mut records = vec![];
+    let mut session = opts.session;
+
+    for rec in CaptureReader::open(&opts.capture)? {
+        let rec = rec?;
+
+        if session.is_none() {
+            session = Some(rec.session);
+        }
+
+        if session == Some(rec.session) {
+            records.push(rec);
+        }
+    }
+
+    match session {
+        None => Err(ReplayError::EmptyCapture)?,
+        Some(id) if records.is_empty() => Err(ReplayError::SessionNotFound(id))?,
+        _ => Ok(records),
+    }
+}
+
+fn replay(opts: &Options) -> Result<usize, Error> {
+    let records = load_session(opts)?;
+
+    let cipher = match opts.keys {
+        Some((ref enc, ref dec)) => Some(SimpleModulus::load(enc, dec)?),
+        None => None,
+    };
+
+    println!(
+        "Replaying session {} ({} packets) against {}",
+        records[0].session,
+        records.len(),
+        opts.addr
+    );
+
+    let stream = TcpStream::connect(&opts.addr)?;
+    stream.set_read_timeout(Some(Duration::from_millis(opts.timeout)))?;
+
+    let mut conn = Connection {
+        stream,
+        frames: FrameDecoder::new(),
+        cipher,
+        serial: 0,
+        xor32: opts.xor32,
+    };
+
+    let mut mismatches = 0;
+
+    for (i, rec) in records.iter().enumerate() {
+        match rec.direction {
+            //Packets the server received are what the client sent.
+            CaptureDirection::Inbound => {
+                conn.send(&rec.frame)?;
+                println!("#{} {} {}", i, rec.direction, hex(&rec.frame));
+            }
+            CaptureDirection::Outbound => match conn.recv()? {
+                Some(ref got) if *got == rec.frame => {
+                    println!("#{} {} {} OK", i, rec.direction, hex(got));
+                }
+                Some(got) => {
+                    mismatches += 1;
+                    let at = first_diff(&rec.frame, &got);
+                    println!("#{} {} MISMATCH at byte {}", i, rec.direction, at);
+                    println!("    expected: {}", hex(&rec.frame));
+                    println!("    received: {}", hex(&got));
+                }
+                None => {
+                    mismatches += 1;
+                    let ms = opts.timeout;
+                    println!("#{} {} MISSING, no response in {}ms", i, rec.direction, ms);
+                    println!("    expected: {}", hex(&rec.frame));
+                }
+            },
+        }
+    }
+
+    Ok(mismatches)
+}
+
+/// Client side of the replayed session.
+struct Connection {
+    stream: TcpStream,
+    frames: FrameDecoder,
+    cipher: Option<SimpleModulus>,
+    serial: u8,
+    xor32: bool,
+}
+
+impl Connection {
+    fn send(&mut self, frame: &[u8]) -> Result<(), Error> {
+        let mut buf = frame.to_vec();
+
+        if self.xor32 {
+            xor32::encode(&mut buf);
+        }
+
+        if buf[0] == 0xC3 || buf[0] == 0xC4 {
+            let cipher = match self.cipher {
+                Some(ref c) => c,
+                None => return Err(ReplayError::MissingKeys)?,
+            };
+
+            buf = cipher.encrypt_frame(&buf, self.serial)?;
+            self.serial = self.serial.wrapping_add(1);
+        }
+
+        self.stream.write_all(&buf)?;
+        Ok(())
+    }
+
+    /// Returns the next plain frame sent by the server, or `None` if it took too long.
+    fn recv(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        let mut buf = [0u8; 4096];
+
+        loop {
+            if let Some(frame) = self.frames.next_frame()? {
+                let mut frame = frame.to_vec();
+
+                if frame[0] == 0xC3 || frame[0] == 0xC4 {
+                    let cipher = match self.cipher {
+                        Some(ref c) => c,
+                        None => return Err(ReplayError::MissingKeys)?,
+                    };
+
+                    frame = cipher.decrypt_frame(&frame)?.0;
+                }
+
+                if self.xor32 {
+                    xor32::decode(&mut frame);
+                }
+
+                return Ok(Some(frame));
+            }
+
+            match self.stream.read(&mut buf) {
+                Ok(0) => return Err(ReplayError::Closed)?,
+                Ok(n) => self.frames.feed(&buf[..n]),
+                //Read timeouts show up as either of these, depending on the platform.
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => return Ok(None),
+                Err(e) => return Err(e)?,
+            }
+        }
+    }
+}
+
+fn first_diff(expected: &[u8], got: &[u8]) -> usize {
+    expected
+        .iter()
+        .zip(got.iter())
+        .position(|(a, b)| a != b)
+        .unwrap_or_else(|| expected.len().min(got.len()))
+}
+
+fn hex(buf: &[u8]) -> String {
+    buf.iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
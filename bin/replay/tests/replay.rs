@@ -0,0 +1,117 @@
+//! Replays a small capture with the `mu-replay` binary against a local listener, which plays the
+//! recorded server and checks what the client side sends.
+
+extern crate mu_proto;
+
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::{self, Command, Output};
+use std::thread;
+
+use mu_proto::{Capture, CaptureDirection, MuPacket};
+
+use CaptureDirection::{Inbound, Outbound};
+
+//A session of two client packets in a row, each followed by a server response.
+const SESSION: &[(CaptureDirection, &[u8])] = &[
+    (Outbound, &[0xC1, 0x04, 0x00, 0x01]),
+    (Inbound, &[0xC1, 0x04, 0xF4, 0x06]),
+    (Inbound, &[0xC1, 0x05, 0xF4, 0x03, 0x02]),
+    (Outbound, &[0xC2, 0x00, 0x07, 0xF4, 0x06, 0x00, 0x00]),
+];
+
+fn write_capture(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("mu-replay-{}-{}.mucap", name, process::id()));
+    let capture = Capture::create(&path).unwrap();
+
+    for (i, &(direction, frame)) in SESSION.iter().enumerate() {
+        capture.record(3, 1, direction, &MuPacket::new(frame).unwrap()).unwrap();
+
+        //Packets of other sessions in the middle must not be replayed.
+        if i == 1 {
+            let other = MuPacket::new(&[0xC1, 0x04, 0x00, 0x02]).unwrap();
+            capture.record(4, 1, Inbound, &other).unwrap();
+        }
+    }
+
+    capture.flush().unwrap();
+    path
+}
+
+/// Plays the server side of `SESSION`, with the responses changed by `respond`, and returns
+/// every byte the replayed client sent until it disconnected.
+fn serve<F>(listener: TcpListener, respond: F) -> thread::JoinHandle<Vec<u8>>
+where
+    F: Fn(&[u8]) -> Vec<u8> + Send + 'static,
+{
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut received = vec![];
+
+        for &(direction, frame) in SESSION {
+            match direction {
+                Inbound => {
+                    let mut buf = vec![0; frame.len()];
+                    stream.read_exact(&mut buf).unwrap();
+                    received.extend_from_slice(&buf);
+                }
+                Outbound => stream.write_all(&respond(frame)).unwrap(),
+            }
+        }
+
+        stream.read_to_end(&mut received).unwrap();
+        received
+    })
+}
+
+fn replay(name: &str, respond: fn(&[u8]) -> Vec<u8>) -> (Output, Vec<u8>) {
+    let path = write_capture(name);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let server = serve(listener, respond);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_mu-replay"))
+        .args([path.to_str().unwrap(), &addr, "--timeout", "2000"])
+        .output()
+        .unwrap();
+
+    fs::remove_file(&path).ok();
+    (output, server.join().unwrap())
+}
+
+fn sent_by_client() -> Vec<u8> {
+    SESSION
+        .iter()
+        .filter(|&&(direction, _)| direction == Inbound)
+        .flat_map(|&(_, frame)| frame.iter().cloned())
+        .collect()
+}
+
+#[test]
+fn replays_client_packets_in_order() {
+    let (output, received) = replay("match", |frame| frame.to_vec());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(output.status.success(), "{}", stdout);
+    assert!(stdout.contains("Replaying session 3 (4 packets)"), "{}", stdout);
+    assert!(stdout.contains("every response matched"), "{}", stdout);
+    assert_eq!(received, sent_by_client());
+}
+
+#[test]
+fn reports_mismatched_responses() {
+    let (output, received) = replay("mismatch", |frame| {
+        let mut frame = frame.to_vec();
+        frame[3] ^= 0xFF;
+        frame
+    });
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert_eq!(output.status.code(), Some(1), "{}", stdout);
+    assert!(stdout.contains("#0 >> MISMATCH at byte 3"), "{}", stdout);
+    assert!(stdout.contains("#3 >> MISMATCH at byte 3"), "{}", stdout);
+    assert!(stdout.contains("with 2 mismatches"), "{}", stdout);
+    assert_eq!(received, sent_by_client());
+}
@@ -0,0 +1,489 @@
+//! Derives `mu_proto::Protocol` and `mu_proto::ProtoField` from the fields of a struct, so
+//! packet layouts are described once instead of hand-writing byte offsets.
+//!
+//! Struct attributes:
+//! - `#[protocol(msg = "ServerInfo")]` ties the struct to its `ProtoMsg` (Protocol only).
+//! - `#[protocol(endian = "little")]` default byte order of the fields. Big endian if omitted.
+//!
+//! Field attributes:
+//! - `#[protocol(endian = "little")]` byte order of this field only.
+//! - `#[protocol(string = 10)]` null padded `String` using exactly 10 bytes.
+//! - `#[protocol(len_prefix = "u16")]` `Vec` preceded by its item count.
+//! - `#[protocol(profiles = "V104d, Season6")]` field only sent to those `ProtoProfile`s.
+//!   Other profiles skip it on the wire and parse it as `Default::default()`.
+//!
+//! Integers, fixed arrays of them and any other `ProtoField` type are supported as well.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+extern crate syn;
+#[macro_use]
+extern crate quote;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use syn::{Attribute, Data, DeriveInput, Expr, Fields, GenericArgument, Ident, LitInt, LitStr,
+          PathArguments, Type};
+
+#[proc_macro_derive(Protocol, attributes(protocol))]
+pub fn derive_protocol(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+
+    expand_protocol(&input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+#[proc_macro_derive(ProtoField, attributes(protocol))]
+pub fn derive_proto_field(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+
+    expand_proto_field(&input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+#[derive(Clone, Copy)]
+enum Endian {
+    Big,
+    Little,
+}
+
+impl Endian {
+    fn parse(lit: &LitStr) -> syn::Result<Endian> {
+        match lit.value().as_str() {
+            "big" => Ok(Endian::Big),
+            "little" => Ok(Endian::Little),
+            _ => Err(syn::Error::new(lit.span(), "expected \"big\" or \"little\"")),
+        }
+    }
+
+    fn tokens(&self) -> TokenStream2 {
+        match *self {
+            Endian::Big => quote!(::mu_proto::Endian::Big),
+            Endian::Little => quote!(::mu_proto::Endian::Little),
+        }
+    }
+}
+
+struct StructAttrs {
+    msg: Option<Ident>,
+    endian: Endian,
+}
+
+impl StructAttrs {
+    fn parse(attrs: &[Attribute]) -> syn::Result<StructAttrs> {
+        let mut res = StructAttrs {
+            msg: None,
+            endian: Endian::Big,
+        };
+
+        for attr in attrs.iter().filter(|a| a.path().is_ident("protocol")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("msg") {
+                    let lit: LitStr = meta.value()?.parse()?;
+                    res.msg = Some(lit.parse()?);
+                    Ok(())
+                } else if meta.path.is_ident("endian") {
+                    res.endian = Endian::parse(&meta.value()?.parse()?)?;
+                    Ok(())
+                } else {
+                    Err(meta.error("unsupported protocol attribute"))
+                }
+            })?;
+        }
+
+        Ok(res)
+    }
+}
+
+enum FieldKind {
+    /// Any `ProtoField` type, integers included.
+    Plain(Type),
+    /// `[T; N]` where `T: ProtoField + Copy + Default`.
+    Array(Type, Expr),
+    /// Null padded string with a fixed length.
+    Str(usize),
+    /// `Vec<T>` preceded by its item count, encoded as the given integer type.
+    Vec(Type, Type),
+}
+
+struct FieldInfo {
+    name: Ident,
+    kind: FieldKind,
+    endian: TokenStream2,
+    profiles: Option<Vec<Ident>>,
+}
+
+impl FieldInfo {
+    fn parse(field: &syn::Field, default: Endian) -> syn::Result<FieldInfo> {
+        let mut endian = default;
+        let mut string = None;
+        let mut len_prefix = None;
+        let mut profiles = None;
+
+        for attr in field.attrs.iter().filter(|a| a.path().is_ident("protocol")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("endian") {
+                    endian = Endian::parse(&meta.value()?.parse()?)?;
+                    Ok(())
+                } else if meta.path.is_ident("string") {
+                    let lit: LitInt = meta.value()?.parse()?;
+                    string = Some(lit.base10_parse::<usize>()?);
+                    Ok(())
+                } else if meta.path.is_ident("len_prefix") {
+                    let lit: LitStr = meta.value()?.parse()?;
+                    len_prefix = Some(lit.parse::<Type>()?);
+                    Ok(())
+                } else if meta.path.is_ident("profiles") {
+                    let lit: LitStr = meta.value()?.parse()?;
+                    profiles = Some(
+                        lit.value()
+                            .split(',')
+                            .map(|p| syn::parse_str::<Ident>(p.trim()))
+                            .map(|p| p.map(|i| Ident::new(&i.to_string(), lit.span())))
+                            .collect::<syn::Result<Vec<_>>>()
+                            .map_err(|e| syn::Error::new(lit.span(), e))?,
+                    );
+                    Ok(())
+                } else {
+                    Err(meta.error("unsupported protocol attribute"))
+                }
+            })?;
+        }
+
+        let name = field.ident.clone().ok_or_else(|| {
+            syn::Error::new_spanned(field, "only structs with named fields are supported")
+        })?;
+
+        let kind = if let Some(len) = string {
+            FieldKind::Str(len)
+        } else if let Some(prefix) = len_prefix {
+            let item = vec_item(&field.ty).ok_or_else(|| {
+                syn::Error::new_spanned(&field.ty, "len_prefix is only supported on Vec fields")
+            })?;
+            FieldKind::Vec(prefix, item)
+        } else if let Type::Array(ref arr) = field.ty {
+            FieldKind::Array((*arr.elem).clone(), arr.len.clone())
+        } else {
+            FieldKind::Plain(field.ty.clone())
+        };
+
+        Ok(FieldInfo {
+            name,
+            kind,
+            endian: endian.tokens(),
+            profiles,
+        })
+    }
+
+    /// Wraps the field code, so it only runs on the profiles the field belongs to.
+    fn gate(&self, code: TokenStream2, otherwise: TokenStream2) -> TokenStream2 {
+        match self.profiles {
+            None => code,
+            Some(ref profiles) => quote! {
+                match __profile {
+                    #(::mu_proto::ProtoProfile::#profiles)|* => { #code }
+                    _ => { #otherwise }
+                }
+            },
+        }
+    }
+
+    fn parse_tokens(&self) -> TokenStream2 {
+        let name = &self.name;
+        let endian = &self.endian;
+
+        let value = match self.kind {
+            FieldKind::Plain(ref ty) => quote! {{
+                let (v, n) = <#ty as ::mu_proto::ProtoField>::read(
+                    &__buf[__idx..],
+                    #endian,
+                    __profile,
+                )?;
+                __idx += n;
+                v
+            }},
+            FieldKind::Array(ref ty, ref len) => quote! {{
+                let mut v: [#ty; #len] = [::std::default::Default::default(); #len];
+                for item in v.iter_mut() {
+                    let (x, n) = <#ty as ::mu_proto::ProtoField>::read(
+                        &__buf[__idx..],
+                        #endian,
+                        __profile,
+                    )?;
+                    *item = x;
+                    __idx += n;
+                }
+                v
+            }},
+            FieldKind::Str(len) => quote! {{
+                if __buf.len() < __idx + #len {
+                    return Err(::mu_proto::MuPacketError::BodyTooShort(__idx + #len, __buf.len()));
+                }
+                let raw = &__buf[__idx..__idx + #len];
+                let end = raw.iter().position(|b| *b == 0).unwrap_or(#len);
+                __idx += #len;
+                ::std::string::String::from_utf8_lossy(&raw[..end]).into_owned()
+            }},
+            FieldKind::Vec(ref prefix, ref ty) => quote! {{
+                let (cnt, n) = <#prefix as ::mu_proto::ProtoField>::read(
+                    &__buf[__idx..],
+                    #endian,
+                    __profile,
+                )?;
+                __idx += n;
+                let mut v = ::std::vec::Vec::new();
+                for _ in 0..cnt {
+                    let (x, n) = <#ty as ::mu_proto::ProtoField>::read(
+                        &__buf[__idx..],
+                        #endian,
+                        __profile,
+                    )?;
+                    v.push(x);
+                    __idx += n;
+                }
+                v
+            }},
+        };
+
+        let value = self.gate(value, quote!(::std::default::Default::default()));
+        quote!(let #name = #value;)
+    }
+
+    fn serialize_tokens(&self) -> TokenStream2 {
+        let name = &self.name;
+        let endian = &self.endian;
+
+        let code = match self.kind {
+            FieldKind::Plain(_) => quote! {
+                __idx += ::mu_proto::ProtoField::write(
+                    &self.#name,
+                    &mut __buf[__idx..],
+                    #endian,
+                    __profile,
+                );
+            },
+            FieldKind::Array(..) => quote! {
+                for item in self.#name.iter() {
+                    __idx += ::mu_proto::ProtoField::write(
+                        item,
+                        &mut __buf[__idx..],
+                        #endian,
+                        __profile,
+                    );
+                }
+            },
+            FieldKind::Str(len) => quote! {{
+                let bytes = self.#name.as_bytes();
+                let len = ::std::cmp::min(bytes.len(), #len);
+                __buf[__idx..__idx + len].copy_from_slice(&bytes[..len]);
+                for b in __buf[__idx + len..__idx + #len].iter_mut() {
+                    *b = 0;
+                }
+                __idx += #len;
+            }},
+            FieldKind::Vec(ref prefix, _) => quote! {
+                __idx += ::mu_proto::ProtoField::write(
+                    &(self.#name.len() as #prefix),
+                    &mut __buf[__idx..],
+                    #endian,
+                    __profile,
+                );
+                for item in self.#name.iter() {
+                    __idx += ::mu_proto::ProtoField::write(
+                        item,
+                        &mut __buf[__idx..],
+                        #endian,
+                        __profile,
+                    );
+                }
+            },
+        };
+
+        self.gate(code, quote!())
+    }
+
+    fn size_tokens(&self) -> TokenStream2 {
+        let name = &self.name;
+
+        let size = match self.kind {
+            FieldKind::Plain(_) => quote!(::mu_proto::ProtoField::len(&self.#name, __profile)),
+            FieldKind::Array(..) => quote! {
+                self.#name.iter().map(|x| ::mu_proto::ProtoField::len(x, __profile)).sum::<usize>()
+            },
+            FieldKind::Str(len) => quote!(#len),
+            FieldKind::Vec(ref prefix, _) => quote! {
+                ::mu_proto::ProtoField::len(&(self.#name.len() as #prefix), __profile)
+                    + self.#name
+                        .iter()
+                        .map(|x| ::mu_proto::ProtoField::len(x, __profile))
+                        .sum::<usize>()
+            },
+        };
+
+        self.gate(size, quote!(0usize))
+    }
+}
+
+/// Returns `T` when the given type is `Vec<T>`.
+fn vec_item(ty: &Type) -> Option<Type> {
+    let path = match *ty {
+        Type::Path(ref p) => &p.path,
+        _ => return None,
+    };
+
+    let seg = path.segments.last()?;
+    if seg.ident != "Vec" {
+        return None;
+    }
+
+    match seg.arguments {
+        PathArguments::AngleBracketed(ref args) => match args.args.first() {
+            Some(GenericArgument::Type(t)) => Some(t.clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Generated bodies of `parse`, `serialize` and `size`, shared by both derives.
+struct Bodies {
+    parse: TokenStream2,
+    serialize: TokenStream2,
+    size: TokenStream2,
+}
+
+fn expand_bodies(input: &DeriveInput, endian: Endian) -> syn::Result<Bodies> {
+    let fields = match input.data {
+        Data::Struct(ref s) => match s.fields {
+            Fields::Named(ref f) => &f.named,
+            Fields::Unit => return Ok(unit_bodies()),
+            Fields::Unnamed(_) => {
+                return Err(syn::Error::new_spanned(
+                    input,
+                    "only structs with named fields are supported",
+                ))
+            }
+        },
+        _ => return Err(syn::Error::new_spanned(input, "only structs are supported")),
+    };
+
+    let infos = fields
+        .iter()
+        .map(|f| FieldInfo::parse(f, endian))
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    let names = infos.iter().map(|f| &f.name).collect::<Vec<_>>();
+    let parses = infos.iter().map(FieldInfo::parse_tokens);
+    let serializes = infos.iter().map(FieldInfo::serialize_tokens);
+    let sizes = infos.iter().map(FieldInfo::size_tokens);
+
+    //Generated locals are prefixed, so they never clash with the names of the fields.
+    Ok(Bodies {
+        parse: quote! {
+            let mut __idx = 0usize;
+            #(#parses)*
+            (Self { #(#names),* }, __idx)
+        },
+        serialize: quote! {
+            let mut __idx = 0usize;
+            #(#serializes)*
+            __idx
+        },
+        size: quote!(0usize #(+ #sizes)*),
+    })
+}
+
+fn unit_bodies() -> Bodies {
+    Bodies {
+        parse: quote!((Self, 0usize)),
+        serialize: quote!(0usize),
+        size: quote!(0usize),
+    }
+}
+
+fn expand_protocol(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let attrs = StructAttrs::parse(&input.attrs)?;
+    let msg = attrs.msg.clone().ok_or_else(|| {
+        syn::Error::new_spanned(input, "#[derive(Protocol)] requires #[protocol(msg = \"...\")]")
+    })?;
+
+    let Bodies {
+        parse,
+        serialize,
+        size,
+    } = expand_bodies(input, attrs.endian)?;
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::mu_proto::Protocol for #name #ty_generics #where_clause {
+            fn msg() -> ::mu_proto::ProtoMsg {
+                ::mu_proto::ProtoMsg::#msg
+            }
+
+            #[allow(unused_mut, unused_assignments, unused_variables)]
+            fn parse(
+                __buf: &[u8],
+                __profile: ::mu_proto::ProtoProfile,
+            ) -> ::std::result::Result<Self, ::mu_proto::MuPacketError> {
+                let (res, _) = { #parse };
+                Ok(res)
+            }
+
+            #[allow(unused_mut, unused_assignments, unused_variables)]
+            fn serialize(&self, __buf: &mut [u8], __profile: ::mu_proto::ProtoProfile) {
+                let _ = { #serialize };
+            }
+
+            #[allow(unused_variables)]
+            fn size(&self, __profile: ::mu_proto::ProtoProfile) -> usize {
+                #size
+            }
+        }
+    })
+}
+
+fn expand_proto_field(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let attrs = StructAttrs::parse(&input.attrs)?;
+
+    let Bodies {
+        parse,
+        serialize,
+        size,
+    } = expand_bodies(input, attrs.endian)?;
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    //Nested structs always use the byte order of their own fields.
+    Ok(quote! {
+        impl #impl_generics ::mu_proto::ProtoField for #name #ty_generics #where_clause {
+            #[allow(unused_mut, unused_assignments, unused_variables)]
+            fn read(
+                __buf: &[u8],
+                _: ::mu_proto::Endian,
+                __profile: ::mu_proto::ProtoProfile,
+            ) -> ::std::result::Result<(Self, usize), ::mu_proto::MuPacketError> {
+                Ok({ #parse })
+            }
+
+            #[allow(unused_mut, unused_assignments, unused_variables)]
+            fn write(
+                &self,
+                __buf: &mut [u8],
+                _: ::mu_proto::Endian,
+                __profile: ::mu_proto::ProtoProfile,
+            ) -> usize {
+                #serialize
+            }
+
+            #[allow(unused_variables)]
+            fn len(&self, __profile: ::mu_proto::ProtoProfile) -> usize {
+                #size
+            }
+        }
+    })
+}
@@ -0,0 +1,135 @@
+//! Layouts generated by the derives, plus the attribute and type errors they must refuse.
+//! Failing inputs live in tests/ui, with the compiler output they're expected to produce.
+
+extern crate mu_proto;
+#[macro_use]
+extern crate mu_proto_derive;
+extern crate trybuild;
+
+use mu_proto::{Endian, MuPacketError, ProtoField, ProtoProfile, Protocol};
+
+#[derive(ProtoField, Debug, PartialEq)]
+struct Endians {
+    big: u16,
+    #[protocol(endian = "little")]
+    little: u16,
+}
+
+#[derive(ProtoField, Debug, PartialEq)]
+#[protocol(endian = "little")]
+struct LittleByDefault {
+    little: u32,
+    #[protocol(endian = "big")]
+    big: u16,
+}
+
+#[derive(ProtoField, Debug, PartialEq)]
+struct Named {
+    #[protocol(string = 4)]
+    name: String,
+    tail: u8,
+}
+
+#[derive(ProtoField, Debug, PartialEq)]
+struct Items {
+    #[protocol(len_prefix = "u8")]
+    items: Vec<u16>,
+    tail: u8,
+}
+
+#[derive(ProtoField, Debug, PartialEq)]
+struct Versioned {
+    code: u8,
+    #[protocol(profiles = "V104d, Season6")]
+    extra: u16,
+}
+
+#[derive(Protocol, Debug, PartialEq)]
+#[protocol(msg = "ServerList")]
+struct Huge {
+    #[protocol(len_prefix = "u32")]
+    body: Vec<u8>,
+}
+
+fn write<F: ProtoField>(field: &F, profile: ProtoProfile) -> Vec<u8> {
+    let mut buf = vec![0xFF; field.len(profile)];
+    assert_eq!(field.write(&mut buf, Endian::Big, profile), buf.len());
+    buf
+}
+
+fn read<F: ProtoField>(buf: &[u8], profile: ProtoProfile) -> F {
+    let (field, n) = F::read(buf, Endian::Big, profile).unwrap();
+    assert_eq!(n, buf.len());
+    field
+}
+
+#[test]
+fn endian_of_struct_and_field() {
+    let profile = ProtoProfile::default();
+
+    let v = Endians { big: 0x0102, little: 0x0304 };
+    let buf = write(&v, profile);
+    assert_eq!(buf, [0x01, 0x02, 0x04, 0x03]);
+    assert_eq!(read::<Endians>(&buf, profile), v);
+
+    let v = LittleByDefault { little: 0x01020304, big: 0x0506 };
+    let buf = write(&v, profile);
+    assert_eq!(buf, [0x04, 0x03, 0x02, 0x01, 0x05, 0x06]);
+    assert_eq!(read::<LittleByDefault>(&buf, profile), v);
+}
+
+#[test]
+fn string_is_null_padded_and_cut() {
+    let profile = ProtoProfile::default();
+
+    let buf = write(&Named { name: "ab".into(), tail: 7 }, profile);
+    assert_eq!(buf, [b'a', b'b', 0, 0, 7]);
+    assert_eq!(read::<Named>(&buf, profile).name, "ab");
+
+    let buf = write(&Named { name: "abcdef".into(), tail: 7 }, profile);
+    assert_eq!(buf, [b'a', b'b', b'c', b'd', 7]);
+    assert_eq!(read::<Named>(&buf, profile).name, "abcd");
+
+    let res = Named::read(b"ab", Endian::Big, profile);
+    assert_eq!(res.unwrap_err(), MuPacketError::BodyTooShort(4, 2));
+}
+
+#[test]
+fn len_prefix_counts_items() {
+    let profile = ProtoProfile::default();
+
+    let v = Items { items: vec![0x0102, 0x0304], tail: 9 };
+    let buf = write(&v, profile);
+    assert_eq!(buf, [2, 0x01, 0x02, 0x03, 0x04, 9]);
+    assert_eq!(read::<Items>(&buf, profile), v);
+}
+
+#[test]
+fn profiles_skip_the_field_elsewhere() {
+    let v = Versioned { code: 1, extra: 0x0203 };
+
+    let buf = write(&v, ProtoProfile::V104d);
+    assert_eq!(buf, [1, 0x02, 0x03]);
+    assert_eq!(read::<Versioned>(&buf, ProtoProfile::V104d), v);
+
+    let buf = write(&v, ProtoProfile::V097d);
+    assert_eq!(buf, [1]);
+    assert_eq!(read::<Versioned>(&buf, ProtoProfile::V097d), Versioned { code: 1, extra: 0 });
+}
+
+#[test]
+fn size_is_never_truncated() {
+    let msg = Huge { body: vec![0; 70_000] };
+    assert_eq!(msg.size(ProtoProfile::default()), 70_004);
+    assert_eq!(
+        msg.to_packet(ProtoProfile::default()).unwrap_err(),
+        MuPacketError::TooLarge(0xC2, 70_009)
+    );
+}
+
+#[test]
+fn ui() {
+    let t = trybuild::TestCases::new();
+    t.pass("tests/ui/pass/*.rs");
+    t.compile_fail("tests/ui/fail/*.rs");
+}
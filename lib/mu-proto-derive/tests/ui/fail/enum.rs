@@ -0,0 +1,9 @@
+#[macro_use]
+extern crate mu_proto_derive;
+
+#[derive(ProtoField)]
+enum Entry {
+    Code(u16),
+}
+
+fn main() {}
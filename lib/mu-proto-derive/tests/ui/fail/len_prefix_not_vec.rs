@@ -0,0 +1,10 @@
+#[macro_use]
+extern crate mu_proto_derive;
+
+#[derive(ProtoField)]
+struct Entry {
+    #[protocol(len_prefix = "u8")]
+    codes: [u16; 4],
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+#[macro_use]
+extern crate mu_proto_derive;
+
+#[derive(Protocol)]
+struct Ping {
+    seq: u32,
+}
+
+fn main() {}
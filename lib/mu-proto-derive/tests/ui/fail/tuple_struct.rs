@@ -0,0 +1,7 @@
+#[macro_use]
+extern crate mu_proto_derive;
+
+#[derive(ProtoField)]
+struct Entry(u16);
+
+fn main() {}
@@ -0,0 +1,10 @@
+#[macro_use]
+extern crate mu_proto_derive;
+
+#[derive(ProtoField)]
+struct Entry {
+    #[protocol(optional)]
+    code: u16,
+}
+
+fn main() {}
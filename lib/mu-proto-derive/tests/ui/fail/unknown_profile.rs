@@ -0,0 +1,10 @@
+#[macro_use]
+extern crate mu_proto_derive;
+
+#[derive(ProtoField)]
+struct Entry {
+    #[protocol(profiles = "V104d, Season7")]
+    code: u16,
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+#[macro_use]
+extern crate mu_proto_derive;
+
+#[derive(Protocol)]
+#[protocol(msg = "Ping", padding = 2)]
+struct Ping {
+    seq: u32,
+}
+
+fn main() {}
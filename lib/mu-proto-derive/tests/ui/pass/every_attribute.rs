@@ -0,0 +1,28 @@
+#[macro_use]
+extern crate mu_proto_derive;
+
+#[derive(ProtoField, Default)]
+#[protocol(endian = "little")]
+struct Entry {
+    code: u16,
+    #[protocol(endian = "big")]
+    load: u8,
+}
+
+#[derive(Protocol)]
+#[protocol(msg = "ServerList", endian = "big")]
+struct List {
+    #[protocol(string = 10)]
+    name: String,
+    #[protocol(len_prefix = "u16")]
+    entries: Vec<Entry>,
+    #[protocol(profiles = "V104d, Season6")]
+    extra: u32,
+    pair: [u8; 2],
+}
+
+#[derive(Protocol)]
+#[protocol(msg = "Ping")]
+struct Empty;
+
+fn main() {}
@@ -0,0 +1,162 @@
+//! Throughput of the path every received packet takes to reach the server handler.
+//!
+//! `event_channel` compares the event queue of `Server` against the plumbing it replaced, a
+//! std mpsc channel plus a shared waker locked on every send. `server` measures the whole
+//! path, from client sockets to `NetworkEvent::ClientPacket`s out of the `Server` stream.
+//!
+//! Run with `cargo bench -p mu-proto --bench events`.
+
+extern crate criterion;
+extern crate futures;
+extern crate mu_proto;
+extern crate tokio;
+
+use std::pin::Pin;
+use std::sync::mpsc as std_mpsc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use futures::{Stream, StreamExt};
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+
+use mu_proto::{NetworkEvent, Server, SessionOptions};
+
+const PRODUCERS: usize = 8;
+const EVENTS_PER_PRODUCER: usize = 10_000;
+
+const CLIENTS: usize = 8;
+const PACKETS_PER_CLIENT: usize = 1_000;
+const BENCH_PORT: u16 = 47201;
+
+/// The previous event queue: every sender locks the waker, sends and wakes the consumer.
+struct NotifyQueue {
+    rx: std_mpsc::Receiver<u32>,
+    waker: Arc<Mutex<Option<Waker>>>,
+}
+
+impl Stream for NotifyQueue {
+    type Item = u32;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u32>> {
+        *self.waker.lock().unwrap() = Some(cx.waker().clone());
+
+        match self.rx.try_recv() {
+            Err(std_mpsc::TryRecvError::Empty) => Poll::Pending,
+            Err(std_mpsc::TryRecvError::Disconnected) => Poll::Ready(None),
+            Ok(evt) => Poll::Ready(Some(evt)),
+        }
+    }
+}
+
+fn notify_queue(rt: &Runtime) {
+    let (tx, rx) = std_mpsc::channel();
+    let waker: Arc<Mutex<Option<Waker>>> = Arc::new(Mutex::new(None));
+
+    for _ in 0..PRODUCERS {
+        let tx = tx.clone();
+        let waker = Arc::clone(&waker);
+
+        rt.spawn(async move {
+            for i in 0..EVENTS_PER_PRODUCER {
+                let waker = waker.lock().unwrap();
+                tx.send(i as u32).unwrap();
+
+                if let Some(ref w) = *waker {
+                    w.wake_by_ref();
+                }
+            }
+        });
+    }
+
+    let mut queue = NotifyQueue { rx, waker };
+    rt.block_on(async {
+        for _ in 0..PRODUCERS * EVENTS_PER_PRODUCER {
+            queue.next().await.unwrap();
+        }
+    });
+}
+
+fn async_queue(rt: &Runtime) {
+    let (tx, mut rx) = mpsc::channel(1024);
+
+    for _ in 0..PRODUCERS {
+        let tx = tx.clone();
+
+        rt.spawn(async move {
+            for i in 0..EVENTS_PER_PRODUCER {
+                tx.send(i as u32).await.unwrap();
+            }
+        });
+    }
+
+    rt.block_on(async {
+        for _ in 0..PRODUCERS * EVENTS_PER_PRODUCER {
+            rx.recv().await.unwrap();
+        }
+    });
+}
+
+fn event_channel(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+
+    let mut group = c.benchmark_group("event_channel");
+    group.throughput(Throughput::Elements((PRODUCERS * EVENTS_PER_PRODUCER) as u64));
+    group.bench_function("std_mpsc_notify", |b| b.iter(|| notify_queue(&rt)));
+    group.bench_function("bounded_async", |b| b.iter(|| async_queue(&rt)));
+    group.finish();
+}
+
+fn server(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let _guard = rt.enter();
+
+    let mut server = Server::new();
+    server
+        .start_tcp("127.0.0.1", BENCH_PORT, 1, SessionOptions::default())
+        .unwrap();
+
+    let mut clients = rt.block_on(async {
+        let mut clients = vec![];
+
+        for _ in 0..CLIENTS {
+            clients.push(TcpStream::connect(("127.0.0.1", BENCH_PORT)).await.unwrap());
+
+            match server.next().await {
+                Some(NetworkEvent::ClientConnected(_)) => (),
+                other => panic!("expected a connection, got {:?}", other),
+            }
+        }
+
+        clients
+    });
+
+    //ConnectResult frames, back to back.
+    let burst: Vec<u8> = [0xC1, 0x04, 0x00, 0x01].repeat(PACKETS_PER_CLIENT);
+
+    let mut group = c.benchmark_group("server");
+    group.throughput(Throughput::Elements((CLIENTS * PACKETS_PER_CLIENT) as u64));
+    group.bench_function("client_packets", |b| {
+        b.iter(|| {
+            rt.block_on(async {
+                for client in clients.iter_mut() {
+                    client.write_all(&burst).await.unwrap();
+                }
+
+                for _ in 0..CLIENTS * PACKETS_PER_CLIENT {
+                    match server.next().await {
+                        Some(NetworkEvent::ClientPacket(_)) => (),
+                        other => panic!("expected a packet, got {:?}", other),
+                    }
+                }
+            })
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, event_channel, server);
+criterion_main!(benches);
@@ -0,0 +1,55 @@
+//! SimpleModulus decryption of C3/C4 frames, followed by XOR32 and message decoding.
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate mu_proto;
+
+use mu_proto::{xor32, FrameDecoder, Message, MuPacket, ProtoProfile, SimpleModulus,
+               SimpleModulusKeys};
+
+//Test only key pair, the corpus was encrypted with ENC_KEY. Never use them on a real server.
+const MODULUS: [u32; 4] = [0x17735, 0x11737, 0x10AB9, 0x1656B];
+const DEC_KEY: [u32; 4] = [0x036BB, 0x0227B, 0x0261D, 0x03DC0];
+const ENC_KEY: [u32; 4] = [0x0586D, 0x01DC4, 0x0FBE8, 0x08AD9];
+const XOR_KEY: [u32; 4] = [0x0965E, 0x0CF44, 0x0FB71, 0x073F7];
+
+fn cipher() -> SimpleModulus {
+    SimpleModulus::new(
+        SimpleModulusKeys {
+            modulus: MODULUS,
+            key: ENC_KEY,
+            xor: XOR_KEY,
+        },
+        SimpleModulusKeys {
+            modulus: MODULUS,
+            key: DEC_KEY,
+            xor: XOR_KEY,
+        },
+    )
+}
+
+fuzz_target!(|data: &[u8]| {
+    let cipher = cipher();
+    let mut frames = FrameDecoder::new();
+    frames.feed(data);
+
+    while let Ok(Some(frame)) = frames.next_frame() {
+        let (mut plain, serial) = match cipher.decrypt_frame(&frame) {
+            Ok(res) => res,
+            Err(_) => continue,
+        };
+
+        //Whatever decrypts must survive an encryption round trip.
+        if let Ok(enc) = cipher.encrypt_frame(&plain, serial) {
+            assert_eq!(cipher.decrypt_frame(&enc).unwrap(), (plain.clone(), serial));
+        }
+
+        xor32::decode(&mut plain);
+
+        if let Ok(pkt) = MuPacket::new(&plain) {
+            for profile in ProtoProfile::all() {
+                let _ = Message::decode(&pkt, *profile);
+            }
+        }
+    }
+});
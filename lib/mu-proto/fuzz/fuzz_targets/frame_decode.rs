@@ -0,0 +1,50 @@
+//! Stream framing followed by packet and message decoding, the path every byte received by a
+//! listener goes through.
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate mu_proto;
+
+use mu_proto::{xor32, FrameDecoder, Message, MuPacket, ProtoProfile};
+
+fuzz_target!(|data: &[u8]| {
+    if data.is_empty() {
+        return;
+    }
+
+    //The first byte picks how many bytes each read delivers, so frames get split anywhere.
+    let chunk = data[0] as usize + 1;
+    let mut frames = FrameDecoder::new();
+
+    for bytes in data[1..].chunks(chunk) {
+        frames.feed(bytes);
+
+        loop {
+            let frame = match frames.next_frame() {
+                Ok(Some(frame)) => frame.to_vec(),
+                Ok(None) => break,
+                //Listeners drop the session on framing errors.
+                Err(_) => return,
+            };
+
+            decode(&frame);
+
+            let mut plain = frame.clone();
+            xor32::decode(&mut plain);
+            decode(&plain);
+        }
+    }
+});
+
+fn decode(frame: &[u8]) {
+    let pkt = match MuPacket::new(frame) {
+        Ok(pkt) => pkt,
+        Err(_) => return,
+    };
+
+    assert_eq!(&pkt.frame()[..], frame);
+
+    for profile in ProtoProfile::all() {
+        let _ = Message::decode(&pkt, *profile);
+    }
+}
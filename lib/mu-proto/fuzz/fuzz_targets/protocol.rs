@@ -0,0 +1,41 @@
+//! Every protocol struct parsed straight from a packet body, on every profile. Whatever parses
+//! must serialize back into something that parses to the same value.
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate mu_proto;
+
+use std::fmt::Debug;
+
+use mu_proto::{ConnectResult, JoinServerStat, ProtoProfile, Protocol, ServerInfo, ServerList};
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 2 {
+        return;
+    }
+
+    //First byte picks the message, second one the profile and the rest is the body.
+    let profiles = ProtoProfile::all();
+    let profile = profiles[data[1] as usize % profiles.len()];
+    let body = &data[2..];
+
+    match data[0] % 4 {
+        0 => round_trip::<ServerInfo>(body, profile),
+        1 => round_trip::<JoinServerStat>(body, profile),
+        2 => round_trip::<ConnectResult>(body, profile),
+        _ => round_trip::<ServerList>(body, profile),
+    }
+});
+
+fn round_trip<P: Protocol + PartialEq + Debug>(body: &[u8], profile: ProtoProfile) {
+    let msg = match P::parse(body, profile) {
+        Ok(msg) => msg,
+        Err(_) => return,
+    };
+
+    let mut buf = vec![0; msg.size(profile)];
+    msg.serialize(&mut buf, profile);
+
+    assert!(buf.len() <= body.len());
+    assert_eq!(P::parse(&buf, profile).unwrap(), msg);
+}
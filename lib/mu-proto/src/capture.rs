@@ -0,0 +1,280 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use failure::Error;
+
+use super::packet::MuPacket;
+
+//File layout: magic and version, followed by records of
+//u64 timestamp (µs since epoch), u32 session id, u8 session kind, u8 direction,
+//u16 frame length and the plain frame. Every number is little endian.
+const CAPTURE_MAGIC: &[u8; 4] = b"MUCP";
+const CAPTURE_VERSION: u8 = 1;
+const RECORD_HEADER_LEN: usize = 8 + 4 + 1 + 1 + 2;
+
+/// Records waiting for the writer thread. Past it, records are dropped instead of holding up
+/// the sessions.
+const CAPTURE_QUEUE_LEN: usize = 4096;
+/// Longest time a record waits on the writer buffer before reaching the file.
+const CAPTURE_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Fail)]
+pub enum CaptureError {
+    #[fail(display = "Failed to open capture file.")]
+    Open,
+    #[fail(display = "Failed to write capture file.")]
+    Write,
+    #[fail(display = "Capture writer is behind, record dropped.")]
+    Full,
+    #[fail(display = "Failed to read capture file.")]
+    Read,
+    #[fail(display = "Not a capture file or unsupported version.")]
+    InvalidHeader,
+    #[fail(display = "Capture record is truncated.")]
+    Truncated,
+    #[fail(display = "Unknown capture direction: {:02X}", _0)]
+    InvalidDirection(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureDirection {
+    /// Received from the remote endpoint.
+    Inbound,
+    /// Sent to the remote endpoint.
+    Outbound,
+}
+
+impl CaptureDirection {
+    fn to_u8(self) -> u8 {
+        match self {
+            CaptureDirection::Inbound => 0,
+            CaptureDirection::Outbound => 1,
+        }
+    }
+
+    fn from_u8(v: u8) -> Result<CaptureDirection, CaptureError> {
+        match v {
+            0 => Ok(CaptureDirection::Inbound),
+            1 => Ok(CaptureDirection::Outbound),
+            _ => Err(CaptureError::InvalidDirection(v)),
+        }
+    }
+}
+
+impl fmt::Display for CaptureDirection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CaptureDirection::Inbound => write!(f, "<<"),
+            CaptureDirection::Outbound => write!(f, ">>"),
+        }
+    }
+}
+
+/// A single packet seen by a session. The frame is kept plain, before XOR32 and SimpleModulus.
+#[derive(Debug, Clone)]
+pub struct CaptureRecord {
+    /// Microseconds since the unix epoch.
+    pub time: u64,
+    pub session: u32,
+    pub kind: u8,
+    pub direction: CaptureDirection,
+    pub frame: Vec<u8>,
+}
+
+enum CaptureCmd {
+    Record(Vec<u8>),
+    //Answers whether everything written so far reached the file.
+    Flush(SyncSender<bool>),
+}
+
+/// Shared handle to a capture file. Every session of every listener given the same handle
+/// appends to the same file, so clones are cheap. Records are written by a thread of their own,
+/// which flushes them periodically and once every handle is dropped.
+#[derive(Clone)]
+pub struct Capture {
+    tx: SyncSender<CaptureCmd>,
+}
+
+impl Capture {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Capture, Error> {
+        let file = File::create(path).map_err(|_| CaptureError::Open)?;
+        let mut out = BufWriter::new(file);
+
+        out.write_all(CAPTURE_MAGIC)
+            .and_then(|_| out.write_all(&[CAPTURE_VERSION]))
+            .map_err(|_| CaptureError::Write)?;
+
+        let (tx, rx) = mpsc::sync_channel(CAPTURE_QUEUE_LEN);
+
+        thread::Builder::new()
+            .name("capture".to_owned())
+            .spawn(move || write_records(out, rx))
+            .map_err(|_| CaptureError::Open)?;
+
+        Ok(Capture { tx })
+    }
+
+    /// Queues the packet to be written. Never blocks, so sessions may record from the runtime.
+    pub fn record(
+        &self,
+        session: u32,
+        kind: u8,
+        direction: CaptureDirection,
+        pkt: &MuPacket,
+    ) -> Result<(), CaptureError> {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() * 1_000_000 + u64::from(d.subsec_micros()))
+            .unwrap_or(0);
+
+        let frame = pkt.frame();
+        let mut rec = Vec::with_capacity(RECORD_HEADER_LEN + frame.len());
+        rec.extend_from_slice(&time.to_le_bytes());
+        rec.extend_from_slice(&session.to_le_bytes());
+        rec.push(kind);
+        rec.push(direction.to_u8());
+        rec.extend_from_slice(&(frame.len() as u16).to_le_bytes());
+        rec.extend_from_slice(frame);
+
+        match self.tx.try_send(CaptureCmd::Record(rec)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(CaptureError::Full),
+            Err(TrySendError::Disconnected(_)) => Err(CaptureError::Write),
+        }
+    }
+
+    /// Waits until every record queued before is on the file. Meant for shutdown, as it
+    /// blocks the calling thread.
+    pub fn flush(&self) -> Result<(), CaptureError> {
+        let (ack_tx, ack_rx) = mpsc::sync_channel(1);
+
+        self.tx
+            .send(CaptureCmd::Flush(ack_tx))
+            .map_err(|_| CaptureError::Write)?;
+
+        match ack_rx.recv() {
+            Ok(true) => Ok(()),
+            _ => Err(CaptureError::Write),
+        }
+    }
+}
+
+fn write_records(mut out: BufWriter<File>, rx: Receiver<CaptureCmd>) {
+    let mut flushed_at = Instant::now();
+
+    loop {
+        let res = match rx.recv_timeout(CAPTURE_FLUSH_INTERVAL) {
+            Ok(CaptureCmd::Record(rec)) => out.write_all(&rec),
+            Ok(CaptureCmd::Flush(ack)) => {
+                let res = out.flush();
+                ack.send(res.is_ok()).ok();
+                res
+            }
+            Err(RecvTimeoutError::Timeout) => Ok(()),
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+
+        //Records keep arriving on a busy server, so the interval is checked after each one.
+        let res = res.and_then(|_| {
+            if flushed_at.elapsed() >= CAPTURE_FLUSH_INTERVAL {
+                flushed_at = Instant::now();
+                out.flush()
+            } else {
+                Ok(())
+            }
+        });
+
+        if let Err(e) = res {
+            //Dropping the receiver makes every later record fail, instead of piling up.
+            println!("Failed to write capture file, capture stopped: {}", e);
+            return;
+        }
+    }
+
+    out.flush().ok();
+}
+
+impl fmt::Debug for Capture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Capture")
+    }
+}
+
+/// Reads back the records of a capture file, in the order they were written.
+pub struct CaptureReader<R> {
+    input: R,
+}
+
+impl CaptureReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<CaptureReader<BufReader<File>>, Error> {
+        let file = File::open(path).map_err(|_| CaptureError::Open)?;
+        Ok(CaptureReader::new(BufReader::new(file))?)
+    }
+}
+
+impl<R: Read> CaptureReader<R> {
+    pub fn new(mut input: R) -> Result<CaptureReader<R>, CaptureError> {
+        let mut hdr = [0u8; 5];
+        input
+            .read_exact(&mut hdr)
+            .map_err(|_| CaptureError::InvalidHeader)?;
+
+        if &hdr[0..4] != CAPTURE_MAGIC || hdr[4] != CAPTURE_VERSION {
+            return Err(CaptureError::InvalidHeader);
+        }
+
+        Ok(CaptureReader { input })
+    }
+
+    /// Returns the next record, or `None` at the end of the file.
+    pub fn next_record(&mut self) -> Result<Option<CaptureRecord>, CaptureError> {
+        let mut hdr = [0u8; RECORD_HEADER_LEN];
+        let mut read = 0;
+
+        //A clean end of file may only happen between records.
+        while read < hdr.len() {
+            match self.input.read(&mut hdr[read..]) {
+                Ok(0) if read == 0 => return Ok(None),
+                Ok(0) => return Err(CaptureError::Truncated),
+                Ok(n) => read += n,
+                Err(_) => return Err(CaptureError::Read),
+            }
+        }
+
+        let mut time = [0u8; 8];
+        time.copy_from_slice(&hdr[0..8]);
+        let mut session = [0u8; 4];
+        session.copy_from_slice(&hdr[8..12]);
+        let len = u16::from(hdr[14]) | u16::from(hdr[15]) << 8;
+
+        let mut frame = vec![0; len as usize];
+        self.input
+            .read_exact(&mut frame)
+            .map_err(|_| CaptureError::Truncated)?;
+
+        Ok(Some(CaptureRecord {
+            time: u64::from_le_bytes(time),
+            session: u32::from_le_bytes(session),
+            kind: hdr[12],
+            direction: CaptureDirection::from_u8(hdr[13])?,
+            frame,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = Result<CaptureRecord, CaptureError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_record() {
+            Ok(Some(rec)) => Some(Ok(rec)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
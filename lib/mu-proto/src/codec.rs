@@ -0,0 +1,147 @@
+use std::sync::Arc;
+
+use bytes::{Bytes, BytesMut};
+use failure::Error;
+use tokio_util::codec::{Decoder, Encoder};
+
+use super::capture::{Capture, CaptureDirection};
+use super::frame::FrameDecoder;
+use super::packet::MuPacket;
+use super::server::SessionOptions;
+use super::simple_modulus::{PacketSerial, SimpleModulus};
+use super::tcp_session::TcpSessionError;
+use super::xor32;
+
+/// Turns the bytes of a stream into plain `MuPacket`s and back, handling the frame header,
+/// SimpleModulus on C3/C4 packets, XOR32 and packet capture. Each direction keeps its own
+/// packet serial, so a reader and a writer should each own a clone.
+#[derive(Clone)]
+pub struct MuCodec {
+    id: u32,
+    kind: u8,
+    cipher: Option<Arc<SimpleModulus>>,
+    serial: PacketSerial,
+    xor32: bool,
+    capture: Option<Capture>,
+}
+
+impl MuCodec {
+    pub fn new(
+        id: u32,
+        kind: u8,
+        cipher: Option<Arc<SimpleModulus>>,
+        opts: &SessionOptions,
+    ) -> MuCodec {
+        MuCodec {
+            id,
+            kind,
+            cipher,
+            serial: PacketSerial::new(),
+            xor32: opts.xor32,
+            capture: opts.capture.clone(),
+        }
+    }
+
+    /// Turns a received frame into its plain form. Plain frames are decoded in place.
+    fn open_frame(&mut self, mut frame: BytesMut) -> Result<Bytes, Error> {
+        if frame[0] == 0xC3 || frame[0] == 0xC4 {
+            let mut plain = self.decrypt(&frame)?;
+
+            if self.xor32 {
+                xor32::decode(&mut plain);
+            }
+
+            return Ok(Bytes::from(plain));
+        }
+
+        if self.xor32 {
+            xor32::decode(&mut frame);
+        }
+
+        Ok(frame.freeze())
+    }
+
+    fn decrypt(&mut self, frame: &[u8]) -> Result<Vec<u8>, Error> {
+        let cipher = match self.cipher {
+            Some(ref c) => c,
+            None => return Err(TcpSessionError::MissingCipher)?,
+        };
+
+        let (plain, serial) = cipher.decrypt_frame(frame)?;
+        self.serial.check(serial)?;
+
+        Ok(plain)
+    }
+
+    /// Returns the bytes to put on the wire. Plain and XOR32 frames are shared by every session
+    /// sending the packet, so a broadcast packet is only copied per session when encrypted.
+    pub fn encode_frame(&mut self, item: &MuPacket) -> Result<Bytes, Error> {
+        if let Some(ref capture) = self.capture {
+            capture.record(self.id, self.kind, CaptureDirection::Outbound, item).ok();
+        }
+
+        let frame = if self.xor32 {
+            item.xor32_frame()
+        } else {
+            item.frame()
+        };
+
+        if !item.is_encrypted() {
+            return Ok(frame.clone());
+        }
+
+        Ok(Bytes::from(self.encrypt(frame)?))
+    }
+
+    fn encrypt(&mut self, buf: &[u8]) -> Result<Vec<u8>, Error> {
+        let cipher = match self.cipher {
+            Some(ref c) => c,
+            None => return Err(TcpSessionError::MissingCipher)?,
+        };
+
+        //Encoded frames are always written in order, so the serial is consumed right away.
+        Ok(cipher.encrypt_frame(buf, self.serial.take())?)
+    }
+}
+
+impl Decoder for MuCodec {
+    type Item = MuPacket;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<MuPacket>, Error> {
+        let len = match FrameDecoder::frame_len(src)? {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+
+        if src.len() < len {
+            src.reserve(len - src.len());
+            return Ok(None);
+        }
+
+        //The frame is split off the read buffer itself, so its bytes are never copied.
+        let frame = self.open_frame(src.split_to(len))?;
+        let pkt = MuPacket::from_bytes(frame)?;
+
+        if let Some(ref capture) = self.capture {
+            capture.record(self.id, self.kind, CaptureDirection::Inbound, &pkt).ok();
+        }
+
+        Ok(Some(pkt))
+    }
+}
+
+impl Encoder<MuPacket> for MuCodec {
+    type Error = Error;
+
+    fn encode(&mut self, item: MuPacket, dst: &mut BytesMut) -> Result<(), Error> {
+        if item.is_empty() {
+            return Err(TcpSessionError::Closed)?;
+        }
+
+        let frame = self.encode_frame(&item)?;
+        dst.extend_from_slice(&frame);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+
+use super::packet::{MuPacket, MuPacketError};
+use super::protocol::{ProtoMsg, Protocol};
+use super::server::SessionRef;
+
+#[derive(Debug, Fail)]
+pub enum DispatchError {
+    #[fail(display = "Unknown packet: {:02X} {:02X} {:02X}", _0, _1, _2)]
+    Unknown(u8, u8, u8),
+    #[fail(display = "No handler registered for {:?}", _0)]
+    Unhandled(ProtoMsg),
+    #[fail(display = "Invalid {:?} packet: {}", _0, _1)]
+    Invalid(ProtoMsg, MuPacketError),
+}
+
+type HandlerFn<H> = Box<dyn Fn(&mut H, SessionRef, &MuPacket) -> Result<(), MuPacketError>>;
+
+/// Routes inbound packets to the handler registered for their message type, decoding the
+/// body into the message struct on the way.
+pub struct Dispatcher<H> {
+    handlers: HashMap<ProtoMsg, HandlerFn<H>>,
+}
+
+impl<H: 'static> Dispatcher<H> {
+    pub fn new() -> Dispatcher<H> {
+        Dispatcher {
+            handlers: HashMap::new(),
+        }
+    }
+
+    pub fn register<P>(&mut self, f: fn(&mut H, SessionRef, P)) -> &mut Self
+    where
+        P: Protocol + 'static,
+    {
+        self.handlers.insert(
+            P::msg(),
+            Box::new(move |handler, session, pkt| {
+                let msg = P::parse(pkt.data(), session.profile)?;
+                f(handler, session, msg);
+                Ok(())
+            }),
+        );
+        self
+    }
+
+    pub fn dispatch(
+        &self,
+        handler: &mut H,
+        session: SessionRef,
+        pkt: &MuPacket,
+    ) -> Result<(), DispatchError> {
+        let msg = match ProtoMsg::lookup(pkt.kind(), pkt.code, pkt.sub_code, session.profile) {
+            Some(msg) => msg,
+            None => return Err(DispatchError::Unknown(pkt.kind(), pkt.code, pkt.sub_code)),
+        };
+
+        match self.handlers.get(&msg) {
+            Some(f) => f(handler, session, pkt).map_err(|e| DispatchError::Invalid(msg, e)),
+            None => Err(DispatchError::Unhandled(msg)),
+        }
+    }
+}
+
+impl<H: 'static> Default for Dispatcher<H> {
+    fn default() -> Dispatcher<H> {
+        Dispatcher::new()
+    }
+}
@@ -0,0 +1,57 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+#[cfg(unix)]
+use std::path::PathBuf;
+
+#[derive(Debug, Fail)]
+pub enum EndpointError {
+    #[fail(display = "Invalid endpoint: {}. Expected <addr>:<port> or unix:<path>", _0)]
+    Invalid(String),
+}
+
+/// Where a session is connected: a TCP address, or a Unix socket for servers on the same host.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Endpoint {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl From<SocketAddr> for Endpoint {
+    fn from(addr: SocketAddr) -> Endpoint {
+        Endpoint::Tcp(addr)
+    }
+}
+
+impl FromStr for Endpoint {
+    type Err = EndpointError;
+
+    fn from_str(s: &str) -> Result<Endpoint, EndpointError> {
+        let invalid = || EndpointError::Invalid(s.to_owned());
+
+        if let Some(path) = s.strip_prefix("unix:") {
+            #[cfg(unix)]
+            return match path {
+                "" => Err(invalid()),
+                path => Ok(Endpoint::Unix(PathBuf::from(path))),
+            };
+
+            #[cfg(not(unix))]
+            return Err(invalid());
+        }
+
+        s.parse().map(Endpoint::Tcp).map_err(|_| invalid())
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Endpoint::Tcp(addr) => write!(f, "{}", addr),
+            #[cfg(unix)]
+            Endpoint::Unix(ref path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+#[derive(Debug, Fail)]
+pub enum IpFilterError {
+    #[fail(display = "Invalid CIDR block: {}. Expected an address, optionally followed by /<bits>", _0)]
+    InvalidCidr(String),
+}
+
+/// A block of addresses in CIDR notation, like `10.0.0.0/8`. A plain address is a block of one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNet {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        //Dual stack listeners see IPv4 peers as IPv4-mapped IPv6 addresses.
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpNet {
+    type Err = IpFilterError;
+
+    fn from_str(s: &str) -> Result<IpNet, IpFilterError> {
+        let invalid = || IpFilterError::InvalidCidr(s.to_owned());
+
+        let (addr, prefix) = match s.find('/') {
+            Some(i) => (&s[..i], Some(&s[i + 1..])),
+            None => (s, None),
+        };
+
+        let addr = addr.trim().parse::<IpAddr>().map_err(|_| invalid())?.to_canonical();
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+
+        let prefix = match prefix {
+            Some(bits) => bits.trim().parse::<u8>().map_err(|_| invalid())?,
+            None => max,
+        };
+
+        if prefix > max {
+            return Err(invalid());
+        }
+
+        Ok(IpNet {
+            addr,
+            prefix,
+        })
+    }
+}
+
+impl fmt::Display for IpNet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Addresses a listener accepts. Denied blocks win over allowed ones, and an empty allow list
+/// allows everyone not denied.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IpRules {
+    pub allow: Vec<IpNet>,
+    pub deny: Vec<IpNet>,
+}
+
+impl IpRules {
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|net| net.contains(ip)) {
+            return false;
+        }
+
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(ip))
+    }
+}
+
+/// Rules checked on every accepted connection. Clones share the rules, so updating any of them
+/// applies to the listeners right away.
+#[derive(Debug, Clone, Default)]
+pub struct IpFilter {
+    rules: Arc<RwLock<IpRules>>,
+}
+
+impl IpFilter {
+    pub fn new(rules: IpRules) -> IpFilter {
+        IpFilter {
+            rules: Arc::new(RwLock::new(rules)),
+        }
+    }
+
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        self.rules.read().unwrap().allows(ip)
+    }
+
+    /// Replaces the rules. Sessions already accepted are kept.
+    pub fn update(&self, rules: IpRules) {
+        *self.rules.write().unwrap() = rules;
+    }
+
+    pub fn rules(&self) -> IpRules {
+        self.rules.read().unwrap().clone()
+    }
+}
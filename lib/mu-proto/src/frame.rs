@@ -0,0 +1,87 @@
+use bytes::BytesMut;
+
+use super::packet::MuPacketError;
+
+/// Biggest frame a C2/C4 header is able to describe.
+pub const MAX_FRAME_LEN: usize = 0xFFFF;
+
+/// Accumulates bytes read from a stream and cuts them into complete MU frames, using the
+/// size field of the C1/C2/C3/C4 header. Partial frames are kept until more bytes arrive.
+/// Frames are split off the read buffer itself, so they share its memory instead of copying.
+pub struct FrameDecoder {
+    buf: BytesMut,
+}
+
+impl FrameDecoder {
+    pub fn new() -> FrameDecoder {
+        FrameDecoder {
+            buf: BytesMut::with_capacity(MAX_FRAME_LEN),
+        }
+    }
+
+    /// Appends freshly read bytes to the pending buffer.
+    pub fn feed(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Gives room for at least `additional` bytes, so a stream can read straight into the buffer.
+    pub fn buffer_mut(&mut self, additional: usize) -> &mut BytesMut {
+        self.buf.reserve(additional);
+        &mut self.buf
+    }
+
+    /// Number of bytes waiting for the rest of their frame.
+    pub fn pending(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Returns the next complete frame, header included, or `None` when more bytes are needed.
+    pub fn next_frame(&mut self) -> Result<Option<BytesMut>, MuPacketError> {
+        let len = match FrameDecoder::frame_len(&self.buf)? {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+
+        if self.pending() < len {
+            return Ok(None);
+        }
+
+        Ok(Some(self.buf.split_to(len)))
+    }
+
+    /// Reads the total frame length from the header, or `None` if the header isn't complete yet.
+    pub fn frame_len(buf: &[u8]) -> Result<Option<usize>, MuPacketError> {
+        if buf.is_empty() {
+            return Ok(None);
+        }
+
+        let (len, size_end) = match buf[0] {
+            0xC1 | 0xC3 => {
+                if buf.len() < 2 {
+                    return Ok(None);
+                }
+                (buf[1] as usize, 2)
+            }
+            0xC2 | 0xC4 => {
+                if buf.len() < 3 {
+                    return Ok(None);
+                }
+                ((buf[1] as usize) << 8 | buf[2] as usize, 3)
+            }
+            kind => return Err(MuPacketError::UnknownKind(kind)),
+        };
+
+        //A frame must at least hold its header and the packet code.
+        if len <= size_end {
+            return Err(MuPacketError::TruncatedHeader(len));
+        }
+
+        Ok(Some(len))
+    }
+}
+
+impl Default for FrameDecoder {
+    fn default() -> FrameDecoder {
+        FrameDecoder::new()
+    }
+}
@@ -1,20 +1,55 @@
-#![feature(proc_macro, conservative_impl_trait, generators)]
-#![feature(const_atomic_usize_new)]
-
-#![cfg_attr(feature="clippy", feature(plugin))]
-#![cfg_attr(feature="clippy", plugin(clippy))]
-
+extern crate bytes;
 extern crate failure;
 #[macro_use] extern crate failure_derive;
 
-extern crate futures_await as futures;
+extern crate fastrand;
+extern crate futures;
+extern crate tokio;
+extern crate tokio_rustls;
+extern crate tokio_util;
+#[macro_use] extern crate mu_proto_derive;
+
+//Lets the code generated by mu-proto-derive use `::mu_proto` paths inside this crate too.
+extern crate self as mu_proto;
 
 mod server;
 mod protocol;
+mod profile;
 mod packet;
+mod dispatch;
+mod queue;
+mod capture;
+mod codec;
+mod frame;
+mod simple_modulus;
 mod tcp_session;
+mod shutdown;
+mod limits;
+mod filter;
+mod reconnect;
+mod tls;
+mod endpoint;
+mod udp;
+mod meta;
+pub mod xor32;
 pub mod prelude;
 
-pub use server::{Server, NetworkError, NetworkEvent};
+pub use server::{Server, DisconnectReason, NetworkError, NetworkEvent, SessionOptions};
+pub use queue::{OverflowPolicy, OverflowPolicyError, QueueStats, SessionQueue};
 pub use protocol::*;
-pub use packet::{MuPacket, MuPacketError};
\ No newline at end of file
+pub use profile::{ProtoProfile, ProtoProfileError};
+pub use packet::{MuPacket, MuPacketError};
+pub use dispatch::{DispatchError, Dispatcher};
+pub use simple_modulus::{PacketSerial, SimpleModulus, SimpleModulusError, SimpleModulusKeys};
+pub use capture::{Capture, CaptureDirection, CaptureError, CaptureReader, CaptureRecord};
+pub use codec::MuCodec;
+pub use tcp_session::TcpSessionWriter;
+pub use frame::FrameDecoder;
+pub use shutdown::ShutdownHandle;
+pub use limits::{ConnectionLimits, LimitError};
+pub use filter::{IpFilter, IpFilterError, IpNet, IpRules};
+pub use reconnect::ReconnectPolicy;
+pub use tls::{TlsConfig, TlsError};
+pub use endpoint::{Endpoint, EndpointError};
+pub use udp::UdpSocketRef;
+pub use meta::{Extensions, SessionStats};
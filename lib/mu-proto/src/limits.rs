@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Fail)]
+pub enum LimitError {
+    #[fail(display = "Address {} is banned", _0)]
+    Banned(IpAddr),
+    #[fail(display = "Listener is at its connection limit")]
+    TooManyConnections,
+    #[fail(display = "Address {} is at its connection limit", _0)]
+    TooManyFromAddress(IpAddr),
+}
+
+/// Protects a listener from hosts that open too many connections or flood them with packets.
+/// Every limit is off unless set.
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionLimits {
+    /// Sessions the listener holds at once.
+    pub max_connections: Option<usize>,
+    /// Sessions a single address holds at once.
+    pub max_per_ip: Option<usize>,
+    /// Packets a session may send per second.
+    pub max_packets_per_sec: Option<u32>,
+    /// Bytes a session may send per second.
+    pub max_bytes_per_sec: Option<u32>,
+    /// How long an address that exceeded a rate stays refused. Offenders are only disconnected
+    /// when unset.
+    pub ban_time: Option<Duration>,
+}
+
+#[derive(Default)]
+struct LimiterState {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+    bans: HashMap<IpAddr, Instant>,
+}
+
+/// Connections and bans of a listener, shared by its accept loop and sessions.
+pub(crate) struct Limiter {
+    limits: ConnectionLimits,
+    state: Mutex<LimiterState>,
+}
+
+impl Limiter {
+    pub fn new(limits: ConnectionLimits) -> Limiter {
+        Limiter {
+            limits,
+            state: Mutex::new(LimiterState::default()),
+        }
+    }
+
+    /// Counts a new connection from `ip`, which lasts until the permit is dropped.
+    pub fn admit(self: &Arc<Self>, ip: IpAddr) -> Result<ConnectionPermit, LimitError> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(until) = state.bans.get(&ip) {
+            if *until > Instant::now() {
+                return Err(LimitError::Banned(ip));
+            }
+            state.bans.remove(&ip);
+        }
+
+        if let Some(max) = self.limits.max_connections {
+            if state.total >= max {
+                return Err(LimitError::TooManyConnections);
+            }
+        }
+
+        let count = state.per_ip.get(&ip).cloned().unwrap_or(0);
+
+        if let Some(max) = self.limits.max_per_ip {
+            if count >= max {
+                return Err(LimitError::TooManyFromAddress(ip));
+            }
+        }
+
+        state.total += 1;
+        state.per_ip.insert(ip, count + 1);
+
+        Ok(ConnectionPermit {
+            limiter: Arc::clone(self),
+            ip,
+        })
+    }
+
+    fn ban(&self, ip: IpAddr) {
+        let ban_time = match self.limits.ban_time {
+            Some(t) => t,
+            None => return,
+        };
+
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+
+        //Expired bans are only dropped when the address comes back, so clean up as bans pile up.
+        state.bans.retain(|_, until| *until > now);
+        state.bans.insert(ip, now + ban_time);
+
+        println!("Banned {} for {}s.", ip, ban_time.as_secs());
+    }
+
+    fn release(&self, ip: IpAddr) {
+        let mut state = self.state.lock().unwrap();
+        state.total -= 1;
+
+        let remove = match state.per_ip.get_mut(&ip) {
+            Some(count) => {
+                *count -= 1;
+                *count == 0
+            }
+            None => false,
+        };
+
+        if remove {
+            state.per_ip.remove(&ip);
+        }
+    }
+}
+
+/// A connection counted by a `Limiter`.
+pub(crate) struct ConnectionPermit {
+    limiter: Arc<Limiter>,
+    ip: IpAddr,
+}
+
+impl ConnectionPermit {
+    /// Refuses new connections from the address of this one, for the ban time of the listener.
+    pub fn ban(&self) {
+        self.limiter.ban(self.ip);
+    }
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.limiter.release(self.ip);
+    }
+}
+
+/// Packets and bytes a session received in the current one second window.
+pub(crate) struct RateLimiter {
+    max_packets: Option<u32>,
+    max_bytes: Option<u32>,
+    window: Instant,
+    packets: u32,
+    bytes: u32,
+}
+
+impl RateLimiter {
+    pub fn new(limits: &ConnectionLimits) -> RateLimiter {
+        RateLimiter {
+            max_packets: limits.max_packets_per_sec,
+            max_bytes: limits.max_bytes_per_sec,
+            window: Instant::now(),
+            packets: 0,
+            bytes: 0,
+        }
+    }
+
+    /// Counts a packet of `len` bytes. Returns false once the session went over a limit.
+    pub fn allow(&mut self, len: usize) -> bool {
+        if self.max_packets.is_none() && self.max_bytes.is_none() {
+            return true;
+        }
+
+        let now = Instant::now();
+
+        if now.duration_since(self.window) >= Duration::from_secs(1) {
+            self.window = now;
+            self.packets = 0;
+            self.bytes = 0;
+        }
+
+        self.packets = self.packets.saturating_add(1);
+        self.bytes = self.bytes.saturating_add(len as u32);
+
+        self.max_packets.is_none_or(|max| self.packets <= max)
+            && self.max_bytes.is_none_or(|max| self.bytes <= max)
+    }
+}
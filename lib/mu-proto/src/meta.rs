@@ -0,0 +1,112 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use super::endpoint::Endpoint;
+
+/// Traffic of a session so far. Sizes are of the plain packets, before XOR32 and encryption.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SessionStats {
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub packets_in: u64,
+    pub packets_out: u64,
+}
+
+/// State of a session shared by every clone of its `SessionRef`.
+#[derive(Debug)]
+pub(crate) struct SessionMeta {
+    pub endpoint: Endpoint,
+    pub connected_at: SystemTime,
+    pub extensions: Extensions,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    packets_in: AtomicU64,
+    packets_out: AtomicU64,
+}
+
+impl SessionMeta {
+    pub fn new(endpoint: Endpoint) -> SessionMeta {
+        SessionMeta {
+            endpoint,
+            connected_at: SystemTime::now(),
+            extensions: Extensions::new(),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            packets_in: AtomicU64::new(0),
+            packets_out: AtomicU64::new(0),
+        }
+    }
+
+    pub fn received(&self, len: usize) {
+        self.bytes_in.fetch_add(len as u64, Ordering::Relaxed);
+        self.packets_in.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn sent(&self, packets: u64, bytes: usize) {
+        self.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.packets_out.fetch_add(packets, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> SessionStats {
+        SessionStats {
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+            packets_in: self.packets_in.load(Ordering::Relaxed),
+            packets_out: self.packets_out.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Values attached to a session by the handler, such as the account id or the auth stage, at
+/// most one per type. Handlers should wrap plain values in a type of their own, so they don't
+/// clash with each other.
+#[derive(Default)]
+pub struct Extensions {
+    map: Mutex<HashMap<TypeId, Box<dyn Any + Send + Sync>>>,
+}
+
+impl Extensions {
+    pub fn new() -> Extensions {
+        Extensions::default()
+    }
+
+    /// Attaches the value, returning the one of the same type it replaced.
+    pub fn insert<T: Any + Send + Sync>(&self, val: T) -> Option<T> {
+        let mut map = self.map.lock().unwrap();
+        map.insert(TypeId::of::<T>(), Box::new(val)).and_then(downcast)
+    }
+
+    /// Copy of the value of this type, as the map stays shared with other clones.
+    pub fn get<T: Any + Send + Sync + Clone>(&self) -> Option<T> {
+        self.with(|val: &mut T| val.clone())
+    }
+
+    /// Runs `f` on the value of this type, to read or change it in place.
+    pub fn with<T: Any + Send + Sync, R, F: FnOnce(&mut T) -> R>(&self, f: F) -> Option<R> {
+        let mut map = self.map.lock().unwrap();
+        map.get_mut(&TypeId::of::<T>()).and_then(|val| val.downcast_mut()).map(f)
+    }
+
+    pub fn remove<T: Any + Send + Sync>(&self) -> Option<T> {
+        let mut map = self.map.lock().unwrap();
+        map.remove(&TypeId::of::<T>()).and_then(downcast)
+    }
+
+    pub fn contains<T: Any + Send + Sync>(&self) -> bool {
+        self.map.lock().unwrap().contains_key(&TypeId::of::<T>())
+    }
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Extensions({})", self.map.lock().unwrap().len())
+    }
+}
+
+fn downcast<T: Any>(val: Box<dyn Any + Send + Sync>) -> Option<T> {
+    (val as Box<dyn Any>).downcast().ok().map(|val| *val)
+}
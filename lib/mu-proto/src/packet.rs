@@ -1,67 +1,114 @@
 use std::fmt;
-use protocol::Protocol;
-use protocol::ProtoMsg;
-use protocol::SUB_CODE_PKTS;
+use std::sync::{Arc, OnceLock};
+use bytes::Bytes;
+use super::protocol::Protocol;
+use super::protocol::ProtoMsg;
+use super::protocol::SUB_CODE_PKTS;
+use super::profile::ProtoProfile;
+use super::xor32;
 
 use failure::Error;
 
-#[derive(Debug, Fail)]
+#[derive(Debug, Fail, Clone, PartialEq)]
 pub enum MuPacketError {
     #[fail(display = "The given buffer is too small to create the packet.")]
     BufferTooSmall,
+    #[fail(display = "Packet header is truncated, only {} bytes received.", _0)]
+    TruncatedHeader(usize),
+    #[fail(display = "Packet header says {} bytes, but {} were received.", _0, _1)]
+    SizeMismatch(usize, usize),
+    #[fail(display = "Unknown packet kind: {:02X}", _0)]
+    UnknownKind(u8),
+    #[fail(display = "Packet body too short: expected {} bytes, received {}.", _0, _1)]
+    BodyTooShort(usize, usize),
+    #[fail(display = "Unknown message: {:02X} {:02X} {:02X}", _0, _1, _2)]
+    UnknownMessage(u8, u8, u8),
+    #[fail(display = "Packet of {} bytes doesn't fit a {:02X} header.", _1, _0)]
+    TooLarge(u8, usize),
 }
 
+/// A whole plain frame, header included. The frame is a reference counted buffer, so cloning a
+/// packet to send it to many sessions never copies its bytes. The same goes for its XOR32 form,
+/// computed by the first session that needs it.
 #[derive(Debug, Clone)]
 pub struct MuPacket {
     kind: u8,
-    sz: u16,
     pub code: u8,
     pub sub_code: u8,
-    pub data: Vec<u8>,
+    frame: Bytes,
+    body: usize,
+    xor32: Arc<OnceLock<Bytes>>,
 }
 
 impl MuPacket {
     pub fn empty() -> MuPacket {
         MuPacket {
             kind: 0xFF,
-            sz: 0,
             code: 0,
             sub_code: 0,
-            data: vec![],
+            frame: Bytes::new(),
+            body: 0,
+            xor32: Arc::default(),
         }
     }
 
-    pub fn new(buffer: &[u8]) -> Option<MuPacket> {
+    pub fn new(buffer: &[u8]) -> Result<MuPacket, MuPacketError> {
+        MuPacket::from_bytes(Bytes::from(buffer.to_vec()))
+    }
+
+    /// Wraps an already received frame without copying it.
+    pub fn from_bytes(buffer: Bytes) -> Result<MuPacket, MuPacketError> {
+        if buffer.is_empty() {
+            return Err(MuPacketError::TruncatedHeader(0));
+        }
+
         let kind = buffer[0];
 
+        let size_len = match kind {
+            0xC1 | 0xC3 => 1,
+            0xC2 | 0xC4 => 2,
+            _ => return Err(MuPacketError::UnknownKind(kind)),
+        };
+
         let mut n = 1;
 
-        let sz = match kind {
-            0xC1 => u16::from(buffer[n]),
-            0xC2 => ((u16::from(buffer[n])) << 8) | u16::from(buffer[n + 1]),
-            _ => {
-                println!("Unkown header received: {:02X}", kind);
-                return None;
-            },
+        //Kind, size and code must be there at least.
+        if buffer.len() < n + size_len + 1 {
+            return Err(MuPacketError::TruncatedHeader(buffer.len()));
+        }
+
+        let sz = if size_len == 1 {
+            u16::from(buffer[n])
+        } else {
+            ((u16::from(buffer[n])) << 8) | u16::from(buffer[n + 1])
         };
 
-        n += if kind == 0xC1 { 1 } else { 2 };
+        if sz as usize != buffer.len() {
+            return Err(MuPacketError::SizeMismatch(sz as usize, buffer.len()));
+        }
+
+        n += size_len;
 
-        let code = buffer[n] as u8;
+        let code = buffer[n];
         n += 1;
 
         let mut sub_code = 0u8;
         if MuPacket::has_sub_code(&code) {
+            if buffer.len() <= n {
+                return Err(MuPacketError::TruncatedHeader(buffer.len()));
+            }
+
             sub_code = buffer[n];
             n += 1;
         }
 
-        Some(MuPacket {
-            kind: kind,
-            sz: sz,
-            code: code,
-            sub_code: sub_code,
-            data: buffer[n..].to_vec(),
+        Ok(MuPacket {
+            kind,
+            code,
+            sub_code,
+            frame: buffer,
+            body: n,
+            xor32: Arc::default(),
         })
     }
 
@@ -69,101 +116,130 @@ impl MuPacket {
         SUB_CODE_PKTS.contains(code)
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.kind == 0xFF
+    pub fn kind(&self) -> u8 {
+        self.kind
     }
 
-    pub fn from_protocol<T: Protocol>(msg: &ProtoMsg, proto: &T) -> MuPacket {
-        let (kind, code, subcode) = msg.parse();
-        let len = proto.size();
-        let mut v = vec![0; len as usize];
-        proto.serialize(&mut v);
-        MuPacket {
-            kind: kind,
-            sz: len + MuPacket::header_len(&kind, &code),
-            code: code,
-            sub_code: subcode,
-            data: v,
-        }
+    /// Packet body, everything after the code and sub code.
+    pub fn data(&self) -> &[u8] {
+        &self.frame[self.body..]
     }
 
-    pub fn header_len(kind: &u8, code: &u8) -> u16 {
-        let res = match *kind {
-            0xC1 => 2, //Header and size u8
-            0xC2 => 3, //Header and size u16
-            _ => panic!("Unsupported!"),
-        };
+    /// The serialized frame, shared with every clone of this packet.
+    pub fn frame(&self) -> &Bytes {
+        &self.frame
+    }
 
-        if MuPacket::has_sub_code(code) {
-            res + 2
-        } else {
-            res + 1
-        }
+    /// The frame obfuscated with XOR32. It doesn't depend on the session, so every clone of this
+    /// packet shares it.
+    pub fn xor32_frame(&self) -> &Bytes {
+        self.xor32.get_or_init(|| {
+            let mut buf = self.frame.to_vec();
+            xor32::encode(&mut buf);
+            Bytes::from(buf)
+        })
     }
 
-    pub fn len(&self) -> usize {
-        MuPacket::header_len(&self.kind, &self.code) as usize + self.data.len()
+    pub fn is_empty(&self) -> bool {
+        self.kind == 0xFF
     }
 
-    pub fn serialize(&self, buf: &mut [u8]) -> Result<usize, Error> {
-        if buf.len() < self.len() {
-            return Err(MuPacketError::BufferTooSmall)?;
-        }
+    /// C3/C4 packets travel encrypted with SimpleModulus, but are kept in plain form here.
+    pub fn is_encrypted(&self) -> bool {
+        self.kind == 0xC3 || self.kind == 0xC4
+    }
 
+    pub fn from_protocol<T: Protocol>(
+        msg: &ProtoMsg,
+        proto: &T,
+        profile: ProtoProfile,
+    ) -> Result<MuPacket, MuPacketError> {
+        let (kind, code, subcode) = profile.header(*msg);
+        let hdr = MuPacket::header_len(&kind, &code)?;
+        let sz = proto.size(profile) + hdr as usize;
+
+        //Header and body are written straight into the final frame, in a single allocation.
+        let mut v = vec![0; sz];
+        MuPacket::write_header(&mut v, kind, sz, code, subcode)?;
+        proto.serialize(&mut v[hdr as usize..], profile);
+
+        Ok(MuPacket {
+            kind,
+            code,
+            sub_code: subcode,
+            frame: Bytes::from(v),
+            body: hdr as usize,
+            xor32: Arc::default(),
+        })
+    }
+
+    fn write_header(
+        buf: &mut [u8],
+        kind: u8,
+        sz: usize,
+        code: u8,
+        sub_code: u8,
+    ) -> Result<(), MuPacketError> {
         let mut idx = 0;
 
-        buf[idx] = self.kind;
+        buf[idx] = kind;
         idx += 1;
 
-        match self.kind {
-            0xC1 => {
-                buf[idx] = self.sz as u8;
+        match kind {
+            0xC1 | 0xC3 => {
+                buf[idx] = u8::try_from(sz).map_err(|_| MuPacketError::TooLarge(kind, sz))?;
                 idx += 1;
             }
-            0xC2 => {
-                buf[idx] = (self.sz >> 8) as u8;
-                buf[idx + 1] = (self.sz) as u8;
+            0xC2 | 0xC4 => {
+                let sz = u16::try_from(sz).map_err(|_| MuPacketError::TooLarge(kind, sz))?;
+                buf[idx..idx + 2].copy_from_slice(&sz.to_be_bytes());
                 idx += 2;
             }
-            _ => panic!("Unsupported!"),
+            _ => return Err(MuPacketError::UnknownKind(kind)),
         };
 
-        buf[idx] = self.code;
+        buf[idx] = code;
         idx += 1;
 
-        if self.sub_code > 0 {
-            buf[idx] = self.sub_code;
-            idx += 1;
+        if MuPacket::has_sub_code(&code) {
+            buf[idx] = sub_code;
         }
 
-        buf[idx..self.len()].clone_from_slice(&self.data);
-
-        Ok(self.len())
+        Ok(())
     }
-}
-
-impl fmt::Display for MuPacket {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:02X} ", self.kind)?;
 
-        match self.kind {
-            0xC1 => {
-                write!(f, "{:02X} ", self.sz as u8)?;
-            }
-            0xC2 => {
-                write!(f, "{:02X} ", (self.sz >> 8) as u8)?;
-                write!(f, "{:02X} ", (self.sz) as u8)?;
-            }
-            _ => panic!("Unsupported!"),
+    pub fn header_len(kind: &u8, code: &u8) -> Result<u16, MuPacketError> {
+        let res = match *kind {
+            0xC1 | 0xC3 => 2, //Header and size u8
+            0xC2 | 0xC4 => 3, //Header and size u16
+            _ => return Err(MuPacketError::UnknownKind(*kind)),
         };
 
-        write!(f, "{:02X} ", self.code)?;
+        if MuPacket::has_sub_code(code) {
+            Ok(res + 2)
+        } else {
+            Ok(res + 1)
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.frame.len()
+    }
 
-        if self.sub_code > 0 {
-            write!(f, "{:02X} ", self.sub_code)?;
+    pub fn serialize(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        if buf.len() < self.len() {
+            return Err(MuPacketError::BufferTooSmall)?;
         }
 
-        for b in &self.data {
+        buf[..self.len()].copy_from_slice(&self.frame);
+
+        Ok(self.len())
+    }
+}
+
+impl fmt::Display for MuPacket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for b in self.frame.iter() {
             write!(f, "{:02X} ", b)?;
         }
         Ok(())
@@ -1,3 +1,17 @@
-pub use server::{Server, NetworkError, NetworkEvent, SessionRef};
-pub use protocol::*;
-pub use packet::{MuPacket, MuPacketError};
\ No newline at end of file
+pub use super::server::{Server, DisconnectReason, NetworkError, NetworkEvent, SessionOptions,
+                        SessionRef};
+pub use super::protocol::*;
+pub use super::profile::ProtoProfile;
+pub use super::packet::{MuPacket, MuPacketError};
+pub use super::queue::OverflowPolicy;
+pub use super::dispatch::{DispatchError, Dispatcher};
+pub use super::simple_modulus::SimpleModulus;
+pub use super::capture::Capture;
+pub use super::shutdown::ShutdownHandle;
+pub use super::limits::ConnectionLimits;
+pub use super::filter::{IpFilter, IpNet, IpRules};
+pub use super::reconnect::ReconnectPolicy;
+pub use super::tls::TlsConfig;
+pub use super::endpoint::Endpoint;
+pub use super::udp::UdpSocketRef;
+pub use super::meta::{Extensions, SessionStats};
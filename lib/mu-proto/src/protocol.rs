@@ -1,173 +1,247 @@
-extern crate util;
+use std::mem;
+
+use super::{MuPacket, MuPacketError};
+use super::profile::ProtoProfile;
+
+/// Declares every known message: its `ProtoMsg` code and the struct that describes its body.
+/// The header each client version uses for it lives on the `ProtoProfile` code tables.
+macro_rules! messages {
+    ($($name:ident,)*) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum ProtoMsg {
+            $($name,)*
+        }
+
+        impl ProtoMsg {
+            pub fn all() -> &'static [ProtoMsg] {
+                &[$(ProtoMsg::$name,)*]
+            }
+        }
+
+        /// An inbound packet decoded into its message struct.
+        #[derive(Debug, Clone, PartialEq)]
+        pub enum Message {
+            $($name($name),)*
+        }
 
-use super::MuPacket;
-use self::util::*;
+        impl Message {
+            pub fn decode(pkt: &MuPacket, profile: ProtoProfile) -> Result<Message, MuPacketError> {
+                match ProtoMsg::lookup(pkt.kind(), pkt.code, pkt.sub_code, profile) {
+                    $(Some(ProtoMsg::$name) => {
+                        Ok(Message::$name($name::parse(pkt.data(), profile)?))
+                    })*
+                    None => Err(MuPacketError::UnknownMessage(pkt.kind(), pkt.code, pkt.sub_code)),
+                }
+            }
+
+            pub fn msg(&self) -> ProtoMsg {
+                match *self {
+                    $(Message::$name(_) => ProtoMsg::$name,)*
+                }
+            }
+
+            /// Bytes the message body takes on the wire.
+            pub fn size(&self, profile: ProtoProfile) -> usize {
+                match *self {
+                    $(Message::$name(ref m) => m.size(profile),)*
+                }
+            }
+        }
+    };
+}
 
-#[derive(Debug)]
-pub enum ProtoMsg {
+messages! {
     ServerInfo,
     JoinServerStat,
     ConnectResult,
     ServerList,
+    Ping,
+    Pong,
 }
 
 pub const SUB_CODE_PKTS: [u8; 1] = [0xF4];
 
 impl ProtoMsg {
-    #[allow(unreachable_patterns)]
-    pub fn parse(&self) -> (u8, u8, u8) {
-        match *self {
-            //Type (C1, C2, C3, C4)
-            //Data Lenght (excluding header)
-            //Code
-            //Sub code (0x00 if none)
-            ProtoMsg::ServerInfo => (0xC1, 0x01, 0x00),
-            ProtoMsg::JoinServerStat => (0xC1, 0x02, 0x00),
-            ProtoMsg::ConnectResult => (0xC1, 0x00, 0x00),
-            ProtoMsg::ServerList => (0xC2, 0xF4, 0x06),
-            _ => panic!("Unimplemented ProtoMsg: {:?}", *self),
-        }
+    /// Finds the message the given profile sends with this header. C3/C4 packets match the
+    /// C1/C2 ones, since the size field is the only thing that tells them apart once decrypted.
+    pub fn lookup(kind: u8, code: u8, sub_code: u8, profile: ProtoProfile) -> Option<ProtoMsg> {
+        let width = size_width(kind)?;
+
+        ProtoMsg::all()
+            .iter()
+            .find(|msg| {
+                let (k, c, s) = profile.header(**msg);
+                size_width(k) == Some(width) && c == code && s == sub_code
+            })
+            .cloned()
     }
 }
 
-pub trait Protocol {
-    fn parse(&[u8]) -> Self;
-    fn serialize(&self, &mut [u8]);
-    fn size(&self) -> u16;
-    fn to_packet(&self) -> MuPacket;
-}
-
-pub struct ServerInfo {
-    pub svr_code: u16,
-    pub ip: [u8; 16],
-    pub port: u16,
-    pub perc: u8,
-    pub usr_cnt: u16,
-    pub acc_cnt: u16,
-    pub mx_usr_cnt: u16,
+fn size_width(kind: u8) -> Option<u8> {
+    match kind {
+        0xC1 | 0xC3 => Some(1),
+        0xC2 | 0xC4 => Some(2),
+        _ => None,
+    }
 }
 
-impl Protocol for ServerInfo {
-    fn parse(buf: &[u8]) -> Self {
-        ServerInfo {
-            svr_code: get_u16(&buf[0..2]),
-            ip: {
-                let mut b = [0; 16];
-                b.copy_from_slice(&buf[2..18]);
-                b
-            },
-            port: get_u16(&buf[18..20]),
-            perc: buf[20],
-            usr_cnt: get_u16(&buf[21..23]),
-            acc_cnt: get_u16(&buf[23..25]),
-            mx_usr_cnt: get_u16(&buf[27..29]),
-        }
+/// Makes sure the packet body holds at least `len` bytes before it gets parsed.
+fn check_len(buf: &[u8], len: usize) -> Result<(), MuPacketError> {
+    if buf.len() < len {
+        Err(MuPacketError::BodyTooShort(len, buf.len()))
+    } else {
+        Ok(())
     }
+}
 
-    fn serialize(&self, buf: &mut [u8]) {
-        set_u16(&mut buf[0..2], self.svr_code);
-        buf[2..18].copy_from_slice(&self.ip);
-        set_u16(&mut buf[18..20], self.port);
-        buf[20] = self.perc;
-        set_u16(&mut buf[21..23], self.usr_cnt);
-        set_u16(&mut buf[23..25], self.acc_cnt);
-        set_u16(&mut buf[25..27], self.mx_usr_cnt);
-    }
+/// A message with its own `ProtoMsg` code. Usually implemented by `#[derive(Protocol)]`.
+/// The layout may change between client versions, hence the profile on every method.
+pub trait Protocol: Sized {
+    fn msg() -> ProtoMsg;
+    fn parse(buf: &[u8], profile: ProtoProfile) -> Result<Self, MuPacketError>;
+    fn serialize(&self, buf: &mut [u8], profile: ProtoProfile);
+    /// Body size, checked against the header kind when the packet is built.
+    fn size(&self, profile: ProtoProfile) -> usize;
 
-    fn size(&self) -> u16 {
-        27
+    /// Fails when the message is too large for the header kind of its profile.
+    fn to_packet(&self, profile: ProtoProfile) -> Result<MuPacket, MuPacketError> {
+        MuPacket::from_protocol(&Self::msg(), self, profile)
     }
+}
 
-    fn to_packet(&self) -> MuPacket {
-        MuPacket::from_protocol(&ProtoMsg::ServerInfo, self)
-    }
+/// Byte order of a single field on the wire.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Endian {
+    Big,
+    Little,
 }
 
-pub struct JoinServerStat {
-    pub queue_cnt: u32,
+/// A value nested inside a `Protocol` message. Implemented for integers and by
+/// `#[derive(ProtoField)]` for structs, like the items of a list.
+pub trait ProtoField: Sized {
+    /// Reads the value from the start of `buf`, returning it with the number of bytes used.
+    fn read(
+        buf: &[u8],
+        endian: Endian,
+        profile: ProtoProfile,
+    ) -> Result<(Self, usize), MuPacketError>;
+    /// Writes the value at the start of `buf`, returning the number of bytes used.
+    fn write(&self, buf: &mut [u8], endian: Endian, profile: ProtoProfile) -> usize;
+    fn len(&self, profile: ProtoProfile) -> usize;
 }
 
-impl Protocol for JoinServerStat {
-    fn parse(buf: &[u8]) -> Self {
-        JoinServerStat { queue_cnt: get_u32(&buf[0..4]) }
-    }
+macro_rules! impl_proto_field_int {
+    ($($ty:ty),*) => {$(
+        impl ProtoField for $ty {
+            fn read(
+                buf: &[u8],
+                endian: Endian,
+                _: ProtoProfile,
+            ) -> Result<(Self, usize), MuPacketError> {
+                let n = mem::size_of::<$ty>();
+                check_len(buf, n)?;
+
+                let mut val = 0u64;
+                for i in 0..n {
+                    let b = match endian {
+                        Endian::Big => buf[i],
+                        Endian::Little => buf[n - 1 - i],
+                    };
+                    val = val << 8 | u64::from(b);
+                }
+
+                Ok((val as $ty, n))
+            }
+
+            fn write(&self, buf: &mut [u8], endian: Endian, _: ProtoProfile) -> usize {
+                let n = mem::size_of::<$ty>();
+                let val = *self as u64;
+
+                for i in 0..n {
+                    let shift = match endian {
+                        Endian::Big => 8 * (n - 1 - i),
+                        Endian::Little => 8 * i,
+                    };
+                    buf[i] = (val >> shift) as u8;
+                }
+
+                n
+            }
+
+            fn len(&self, _: ProtoProfile) -> usize {
+                mem::size_of::<$ty>()
+            }
+        }
+    )*};
+}
 
-    fn serialize(&self, buf: &mut [u8]) {
-        set_u32(&mut buf[0..2], self.queue_cnt);
-    }
+impl_proto_field_int!(u8, u16, u32, u64, i8, i16, i32, i64);
 
-    fn size(&self) -> u16 {
-        4
-    }
+#[derive(Protocol, Debug, Clone, PartialEq)]
+#[protocol(msg = "ServerInfo")]
+pub struct ServerInfo {
+    pub svr_code: u16,
+    pub ip: [u8; 16],
+    pub port: u16,
+    pub perc: u8,
+    pub usr_cnt: u16,
+    pub acc_cnt: u16,
+    pub mx_usr_cnt: u16,
+}
 
-    fn to_packet(&self) -> MuPacket {
-        MuPacket::from_protocol(&ProtoMsg::JoinServerStat, self)
-    }
+#[derive(Protocol, Debug, Clone, PartialEq)]
+#[protocol(msg = "JoinServerStat")]
+pub struct JoinServerStat {
+    pub queue_cnt: u32,
 }
 
+#[derive(Protocol, Debug, Clone, PartialEq)]
+#[protocol(msg = "ConnectResult")]
 pub struct ConnectResult {
     pub res: u8,
 }
 
-impl Protocol for ConnectResult {
-    fn parse(buf: &[u8]) -> Self {
-        ConnectResult { res: buf[0] }
-    }
-
-    fn serialize(&self, buf: &mut [u8]) {
-        buf[0] = self.res;
-    }
-
-    fn size(&self) -> u16 {
-        1
-    }
-
-    fn to_packet(&self) -> MuPacket {
-        MuPacket::from_protocol(&ProtoMsg::ConnectResult, self)
-    }
+#[derive(ProtoField, Debug, Clone, PartialEq)]
+pub struct ServerListEntry {
+    //Retail clients read the entries as plain structs, unlike the big endian count before them.
+    #[protocol(endian = "little")]
+    pub idx: u16,
+    pub load: u8,
+    #[protocol(profiles = "V104d, Season6")]
+    pub unk: u8, //Always 0xFF, 0.97d clients don't expect it.
 }
 
+#[derive(Protocol, Debug, Clone, PartialEq, Default)]
+#[protocol(msg = "ServerList")]
 pub struct ServerList {
-    pub cnt: u16,
-    data: Vec<(u16, u8)>,
+    #[protocol(len_prefix = "u16")]
+    pub servers: Vec<ServerListEntry>,
 }
 
 impl ServerList {
-    pub fn new(cnt: u16) -> Self {
-        ServerList {
-            cnt: cnt,
-            data: vec![],
-        }
+    pub fn new() -> Self {
+        ServerList { servers: vec![] }
     }
 
     pub fn add(&mut self, idx: u16, load: u8) {
-        self.data.push((idx, load));
+        self.servers.push(ServerListEntry {
+            idx,
+            load,
+            unk: 0xFF,
+        });
     }
 }
 
-impl Protocol for ServerList {
-    fn parse(_: &[u8]) -> Self {
-        panic!("not used");
-    }
-
-    fn serialize(&self, buf: &mut [u8]) {
-        set_u16(&mut buf[0..2], self.cnt);
-
-        let mut i = 2;
-        for &(idx, load) in &self.data {
-            set_u16(&mut buf[i..i + 2], idx); //The range end is exclusive.
-            buf[i + 2] = load;
-            buf[i + 3] = 0xFF; //Unkown data.
-
-            i += 4;
-        }
-    }
-
-    fn size(&self) -> u16 {
-        2 + (self.data.len() * 4) as u16 //The size of each item is 4, because there is a fixed byte (0xCC)
-    }
+/// Heartbeat of internal links. Sessions with a heartbeat send it periodically and answer it
+/// with a `Pong` carrying the same sequence, without ever handing either to the handler.
+#[derive(Protocol, Debug, Clone, PartialEq)]
+#[protocol(msg = "Ping")]
+pub struct Ping {
+    pub seq: u32,
+}
 
-    fn to_packet(&self) -> MuPacket {
-        MuPacket::from_protocol(&ProtoMsg::ServerList, self)
-    }
+#[derive(Protocol, Debug, Clone, PartialEq)]
+#[protocol(msg = "Pong")]
+pub struct Pong {
+    pub seq: u32,
 }
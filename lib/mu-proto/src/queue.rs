@@ -0,0 +1,269 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::pin::pin;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::sync::Notify;
+
+use super::packet::MuPacket;
+use super::server::NetworkError;
+
+/// Packets a session holds when its listener doesn't say otherwise.
+pub const DEFAULT_QUEUE_LEN: usize = 100;
+
+#[derive(Debug, Fail)]
+pub enum OverflowPolicyError {
+    #[fail(
+        display = "Unknown overflow policy: {}. Expected disconnect, drop_oldest, drop_newest or block:<ms>",
+        _0
+    )]
+    Unknown(String),
+}
+
+/// What a session does when a packet is sent while its outbound queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// Closes the session, dropping every queued packet. Slow clients can't hold memory.
+    #[default]
+    Disconnect,
+    /// Drops the oldest queued packet to make room. Fits periodic updates, where only the
+    /// latest one matters.
+    DropOldest,
+    /// Drops the packet being sent, keeping what was queued before it.
+    DropNewest,
+    /// Waits for room, failing the send after the timeout. Only async sends through
+    /// `SessionRef::send_async` can wait. Sync sends can't, so on a full queue they fail right
+    /// away and the session stays open. Listeners whose handlers only send synchronously
+    /// shouldn't use it.
+    Block(Duration),
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = OverflowPolicyError;
+
+    fn from_str(s: &str) -> Result<OverflowPolicy, OverflowPolicyError> {
+        let unknown = || OverflowPolicyError::Unknown(s.to_owned());
+
+        match s.to_ascii_lowercase().as_str() {
+            "disconnect" => Ok(OverflowPolicy::Disconnect),
+            "drop_oldest" => Ok(OverflowPolicy::DropOldest),
+            "drop_newest" => Ok(OverflowPolicy::DropNewest),
+            other if other.starts_with("block:") => other["block:".len()..]
+                .parse()
+                .map(|ms| OverflowPolicy::Block(Duration::from_millis(ms)))
+                .map_err(|_| unknown()),
+            _ => Err(unknown()),
+        }
+    }
+}
+
+impl fmt::Display for OverflowPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OverflowPolicy::Disconnect => write!(f, "disconnect"),
+            OverflowPolicy::DropOldest => write!(f, "drop_oldest"),
+            OverflowPolicy::DropNewest => write!(f, "drop_newest"),
+            OverflowPolicy::Block(t) => write!(f, "block:{}", t.as_millis()),
+        }
+    }
+}
+
+/// Snapshot of the outbound queue of a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct QueueStats {
+    /// Packets waiting to be written right now.
+    pub depth: usize,
+    /// Most packets ever waiting at once.
+    pub high_water: usize,
+    /// Packets dropped by the overflow policy.
+    pub dropped: usize,
+}
+
+struct QueueState {
+    packets: VecDeque<MuPacket>,
+    //No packets are accepted anymore. The writer still drains what is left.
+    closed: bool,
+}
+
+/// Outbound packets of a session, shared by every `SessionRef` of it and its writer task.
+pub struct SessionQueue {
+    state: Mutex<QueueState>,
+    //Wakes senders waiting by `OverflowPolicy::Block`.
+    space: Notify,
+    //Wakes the writer task.
+    ready: Notify,
+    capacity: usize,
+    policy: OverflowPolicy,
+    high_water: AtomicUsize,
+    dropped: AtomicUsize,
+}
+
+impl SessionQueue {
+    pub fn new(capacity: usize, policy: OverflowPolicy) -> SessionQueue {
+        SessionQueue {
+            state: Mutex::new(QueueState {
+                packets: VecDeque::with_capacity(capacity),
+                closed: false,
+            }),
+            space: Notify::new(),
+            ready: Notify::new(),
+            capacity: capacity.max(1),
+            policy,
+            high_water: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    /// Queues a packet to be written, applying the overflow policy when the queue is full. With
+    /// `OverflowPolicy::Block`, a full queue fails the send, as only `push_wait` may wait.
+    pub fn push(&self, pkt: MuPacket) -> Result<(), NetworkError> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.closed {
+            return Err(NetworkError::SessionDisconnected);
+        }
+
+        if state.packets.len() >= self.capacity {
+            match self.policy {
+                OverflowPolicy::Disconnect => {
+                    self.dropped.fetch_add(state.packets.len() + 1, Ordering::Relaxed);
+                    state.packets.clear();
+                    state.closed = true;
+                    self.ready.notify_one();
+                    return Err(NetworkError::SessionOverflow);
+                }
+                OverflowPolicy::DropOldest => {
+                    state.packets.pop_front();
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                OverflowPolicy::DropNewest => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    return Ok(());
+                }
+                OverflowPolicy::Block(_) => return Err(NetworkError::SessionSendError),
+            }
+        }
+
+        state.packets.push_back(pkt);
+        self.high_water.fetch_max(state.packets.len(), Ordering::Relaxed);
+        self.ready.notify_one();
+
+        Ok(())
+    }
+
+    /// Queues a packet, waiting for room up to the timeout of `OverflowPolicy::Block`. Other
+    /// policies behave as in `push`.
+    pub async fn push_wait(&self, pkt: MuPacket) -> Result<(), NetworkError> {
+        let timeout = match self.policy {
+            OverflowPolicy::Block(timeout) => timeout,
+            _ => return self.push(pkt),
+        };
+
+        let wait = async {
+            loop {
+                //Registered before looking at the queue, so room made meanwhile isn't missed.
+                let mut space = pin!(self.space.notified());
+                space.as_mut().enable();
+
+                {
+                    let mut state = self.state.lock().unwrap();
+
+                    if state.closed {
+                        return Err(NetworkError::SessionDisconnected);
+                    }
+
+                    if state.packets.len() < self.capacity {
+                        state.packets.push_back(pkt);
+                        self.high_water.fetch_max(state.packets.len(), Ordering::Relaxed);
+                        self.ready.notify_one();
+                        return Ok(());
+                    }
+                }
+
+                space.await;
+            }
+        };
+
+        tokio::time::timeout(timeout, wait)
+            .await
+            .unwrap_or(Err(NetworkError::SessionSendError))
+    }
+
+    /// Queues a packet only if there is room, whatever the policy. Never blocks, so tasks of
+    /// the runtime may use it.
+    pub fn try_push(&self, pkt: MuPacket) -> Result<(), NetworkError> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.closed {
+            return Err(NetworkError::SessionDisconnected);
+        }
+
+        if state.packets.len() >= self.capacity {
+            return Err(NetworkError::SessionSendError);
+        }
+
+        state.packets.push_back(pkt);
+        self.high_water.fetch_max(state.packets.len(), Ordering::Relaxed);
+        self.ready.notify_one();
+
+        Ok(())
+    }
+
+    /// Stops accepting packets. Those already queued are still written.
+    pub fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        self.space.notify_waiters();
+        self.ready.notify_one();
+    }
+
+    /// Stops accepting packets and forgets the queued ones, once the peer can't get them.
+    pub fn shutdown(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.packets.clear();
+        state.closed = true;
+        self.space.notify_waiters();
+    }
+
+    /// Next packet to write, or `None` once the queue is closed and drained.
+    pub async fn pop(&self) -> Option<MuPacket> {
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
+
+                if let Some(pkt) = state.packets.pop_front() {
+                    self.space.notify_one();
+                    return Some(pkt);
+                }
+
+                if state.closed {
+                    return None;
+                }
+            }
+
+            //A notification sent before this point is kept, so none of them gets lost.
+            self.ready.notified().await;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.state.lock().unwrap().packets.is_empty()
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        QueueStats {
+            depth: self.state.lock().unwrap().packets.len(),
+            high_water: self.high_water.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl fmt::Debug for SessionQueue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SessionQueue({}, {:?})", self.capacity, self.stats())
+    }
+}
@@ -0,0 +1,42 @@
+use std::time::Duration;
+
+/// How connections started by `connect_to` retry after failing to connect or being lost. Each
+/// attempt goes through every endpoint in order, waiting the delay only after all of them failed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReconnectPolicy {
+    /// Delay after the first failed attempt.
+    pub initial_delay: Duration,
+    /// Cap of the delay, which doubles after each failed attempt.
+    pub max_delay: Duration,
+    /// Fraction of the delay randomly added or taken, so links lost together don't retry together.
+    pub jitter: f64,
+    /// Attempts before giving up on the link, or none to retry forever.
+    pub max_attempts: Option<u32>,
+}
+
+impl ReconnectPolicy {
+    /// Delay after the failed attempt `attempt`, counting from 1.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        let delay = self.initial_delay.saturating_mul(factor).min(self.max_delay);
+
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        delay.mul_f64(1.0 + jitter * (fastrand::f64() * 2.0 - 1.0))
+    }
+
+    /// Whether `attempts` failed attempts exhaust the policy.
+    pub fn gives_up(&self, attempts: u32) -> bool {
+        self.max_attempts.is_some_and(|max| attempts >= max)
+    }
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> ReconnectPolicy {
+        ReconnectPolicy {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+            jitter: 0.2,
+            max_attempts: None,
+        }
+    }
+}
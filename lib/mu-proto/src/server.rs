@@ -1,36 +1,65 @@
-extern crate tokio_core;
-extern crate tokio_io;
-extern crate tokio_timer;
-
-use futures::prelude::*;
-use futures::task::Task;
-use futures::task;
-use futures::sync::mpsc as f_mpsc;
+use futures::future::BoxFuture;
+use futures::{Future, FutureExt, SinkExt, Stream, StreamExt};
 
 use failure::Error;
 
-use self::tokio_core::net::{TcpListener, TcpStream};
-use self::tokio_core::reactor::Handle;
-use self::tokio_io::io::ReadHalf;
-
-use self::tokio_timer::{Timer, TimerError};
+use tokio::io::{AsyncRead, AsyncWrite, ReadHalf, WriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::time::{interval_at, sleep, timeout, Instant};
+use tokio_util::sync::{CancellationToken, WaitForCancellationFutureOwned};
+use tokio_util::task::TaskTracker;
 
 use std::sync::{Arc, Mutex};
-use std::sync::mpsc::{Receiver, Sender};
-use std::sync::mpsc;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::convert::From;
 use std::collections::HashMap;
 use std::net::{AddrParseError, SocketAddr};
 use std::hash::{Hash, Hasher};
-use std::io;
-use std::time::Duration;
+use std::net;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::fmt;
+use std::time::{Duration, SystemTime};
+#[cfg(unix)]
+use std::fs;
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::os::unix::net as unix_net;
+#[cfg(unix)]
+use std::path::PathBuf;
 
-use super::tcp_session::{TcpSession, TcpSessionError, TcpSessionReader};
-use super::packet::MuPacket;
+use super::tcp_session::{self, TcpSessionReader, TcpSessionWriter};
+use super::packet::{MuPacket, MuPacketError};
+use super::simple_modulus::SimpleModulus;
+use super::capture::Capture;
+use super::profile::ProtoProfile;
+use super::protocol::{Ping, Pong, ProtoMsg, Protocol};
+use super::queue::{OverflowPolicy, QueueStats, SessionQueue, DEFAULT_QUEUE_LEN};
+use super::shutdown::ShutdownHandle;
+use super::limits::{ConnectionLimits, ConnectionPermit, Limiter, RateLimiter};
+use super::filter::IpFilter;
+use super::reconnect::ReconnectPolicy;
+use super::tls::TlsConfig;
+use super::endpoint::Endpoint;
+use super::udp::{UdpSocketRef, MAX_DATAGRAM_LEN};
+use super::meta::{Extensions, SessionMeta, SessionStats};
 
 static SESSION_ID_COUNTER: AtomicUsize = AtomicUsize::new(1);
 
+/// Events a server holds before sessions wait for the handler to catch up.
+const EVENT_QUEUE_LEN: usize = 1024;
+
+/// Time peers get to complete the TLS handshake.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time sessions get to write their queued packets on shutdown, unless the server says otherwise.
+const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(Debug, Fail)]
 pub enum NetworkError {
     #[fail(display = "You shouldn't see this.")]
@@ -39,6 +68,14 @@ pub enum NetworkError {
     InvalidAddress,
     #[fail(display = "Failed to bind on TCP address and port")]
     TcpBindError,
+    #[fail(display = "Failed to bind on Unix socket path")]
+    UnixBindError,
+    #[fail(display = "Failed to bind on UDP address and port")]
+    UdpBindError,
+    #[fail(display = "Failed to send datagram")]
+    DatagramSendError,
+    #[fail(display = "Datagram ends in the middle of a packet")]
+    TruncatedDatagram,
     #[fail(display = "Endpoint was disconnected")]
     Disconnected,
     #[fail(display = "Failed to write on TX channel")]
@@ -51,8 +88,12 @@ pub enum NetworkError {
     SessionSendError,
     #[fail(display = "Session was disconnected")]
     SessionDisconnected,
+    #[fail(display = "Session outbound queue overflowed and it was closed")]
+    SessionOverflow,
     #[fail(display = "Failed to execute a internal timer")]
     InternalTimerError,
+    #[fail(display = "Failed to build the packet: {}", _0)]
+    PacketError(MuPacketError),
 }
 
 impl From<AddrParseError> for NetworkError {
@@ -61,52 +102,198 @@ impl From<AddrParseError> for NetworkError {
     }
 }
 
-impl From<TimerError> for NetworkError {
-    fn from(_err: TimerError) -> NetworkError {
-        NetworkError::InternalTimerError
+impl From<MuPacketError> for NetworkError {
+    fn from(err: MuPacketError) -> NetworkError {
+        NetworkError::PacketError(err)
+    }
+}
+
+/// Settings applied to every session of a listener or outgoing connection.
+#[derive(Clone, Debug)]
+pub struct SessionOptions {
+    /// Decodes inbound and encodes outbound packets with the client XOR32 key.
+    /// Only retail client listeners should enable it, internal links stay plain.
+    pub xor32: bool,
+    /// Client version spoken on this listener, which picks message headers and layouts.
+    pub profile: ProtoProfile,
+    /// Records every packet sent and received by the sessions, in plain form.
+    pub capture: Option<Capture>,
+    /// Packets a session holds while its socket can't take them.
+    pub queue_len: usize,
+    /// What to do with packets sent while the queue is full.
+    pub overflow: OverflowPolicy,
+    /// Disconnects sessions that receive nothing for this long, like half-open connections.
+    pub idle_timeout: Option<Duration>,
+    /// Sends a `Ping` this often and answers the ones received. Meant for links between our
+    /// own servers, where both sides should enable it. Without an idle timeout, a peer that
+    /// misses three heartbeats in a row is disconnected.
+    pub heartbeat: Option<Duration>,
+    /// Connection caps, packet rates and bans of listeners. Outgoing connections only apply
+    /// the rates, without banning.
+    pub limits: ConnectionLimits,
+    /// Addresses a listener accepts connections from. Unused by outgoing connections.
+    pub filter: Option<IpFilter>,
+    /// How outgoing connections retry. Unused by listeners.
+    pub reconnect: ReconnectPolicy,
+    /// Mutual TLS, for links between our servers. Retail clients don't speak it.
+    pub tls: Option<TlsConfig>,
+}
+
+impl SessionOptions {
+    fn read_timeout(&self) -> Option<Duration> {
+        self.idle_timeout.or_else(|| self.heartbeat.map(|t| t * 3))
+    }
+}
+
+impl Default for SessionOptions {
+    fn default() -> SessionOptions {
+        SessionOptions {
+            xor32: false,
+            profile: ProtoProfile::default(),
+            capture: None,
+            queue_len: DEFAULT_QUEUE_LEN,
+            overflow: OverflowPolicy::default(),
+            idle_timeout: None,
+            heartbeat: None,
+            limits: ConnectionLimits::default(),
+            filter: None,
+            reconnect: ReconnectPolicy::default(),
+            tls: None,
+        }
+    }
+}
+
+/// Why a session ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// The connection was closed, by either side.
+    Closed,
+    /// Nothing was received within the idle timeout of the session.
+    Idle,
+    /// The peer sent something invalid or the connection failed.
+    Error,
+    /// The peer sent more packets or bytes than the limits of the listener allow.
+    Flood,
+}
+
+impl fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DisconnectReason::Closed => write!(f, "closed"),
+            DisconnectReason::Idle => write!(f, "idle"),
+            DisconnectReason::Error => write!(f, "error"),
+            DisconnectReason::Flood => write!(f, "flood"),
+        }
     }
 }
 
 #[derive(Debug)]
 pub enum NetworkEvent {
     ClientConnected(SessionRef),
-    ClientDisconnected((u32, u8)),
+    ClientDisconnected((u32, u8, DisconnectReason)),
     ClientPacket((SessionRef, MuPacket)),
+    /// A connection started by `connect_to` was lost. `ClientDisconnected` came right before it
+    /// and the server is already trying to get it back.
+    LinkLost((u8, Endpoint)),
+    /// Attempt to get a lost link back, counting from 1.
+    Reconnecting((u8, u32)),
+    /// A lost link is back, maybe on another endpoint. `ClientConnected` came right before it.
+    LinkRestored(SessionRef),
+    /// The reconnect policy gave up on a link after the given attempts. It isn't retried anymore.
+    LinkFailed((u8, u32)),
+    /// A packet received by a socket of `start_udp`, along with the address that sent it.
+    Datagram((UdpSocketRef, SocketAddr, MuPacket)),
+    /// The server stopped accepting connections. Sessions are still open, so the handler can
+    /// send them a last packet. They are closed once the server is polled again.
+    ShuttingDown,
 }
 
 #[derive(Clone, Debug)]
 pub struct SessionRef {
     pub id: u32,
     pub kind: u8,
-    tx: f_mpsc::Sender<MuPacket>,
-    addr: SocketAddr,
+    pub profile: ProtoProfile,
+    queue: Arc<SessionQueue>,
+    meta: Arc<SessionMeta>,
 }
 
 impl SessionRef {
-    pub fn new(id: u32, kind: u8, tx: f_mpsc::Sender<MuPacket>, addr: SocketAddr) -> Self {
+    pub fn new(
+        id: u32,
+        kind: u8,
+        profile: ProtoProfile,
+        queue: Arc<SessionQueue>,
+        endpoint: Endpoint,
+    ) -> Self {
         SessionRef {
-            id: id,
-            kind: kind,
-            tx: tx,
-            addr: addr,
+            id,
+            kind,
+            profile,
+            queue,
+            meta: Arc::new(SessionMeta::new(endpoint)),
         }
     }
 
+    /// Address of the peer, or the path of the Unix socket it came through.
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.meta.endpoint
+    }
+
+    /// Address of the peer, unless it came through a Unix socket.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        match self.meta.endpoint {
+            Endpoint::Tcp(addr) => Some(addr),
+            #[cfg(unix)]
+            Endpoint::Unix(_) => None,
+        }
+    }
+
+    pub fn connected_at(&self) -> SystemTime {
+        self.meta.connected_at
+    }
+
+    /// Bytes and packets received and written so far.
+    pub fn stats(&self) -> SessionStats {
+        self.meta.stats()
+    }
+
+    /// State attached to the session by the handler, shared by every clone of this ref.
+    pub fn extensions(&self) -> &Extensions {
+        &self.meta.extensions
+    }
+
+    /// Closes the session once every packet sent before is written.
     pub fn close(&mut self) -> Result<(), NetworkError> {
-        self.send(MuPacket::empty())
+        self.queue.close();
+        Ok(())
     }
 
+    /// Queues the packet, following the overflow policy of the listener when the queue is full.
     pub fn send(&mut self, pkt: MuPacket) -> Result<(), NetworkError> {
-        match self.tx.try_send(pkt) {
-            Ok(_) => Ok(()),
-            Err(err) => {
-                if err.is_disconnected() {
-                    Err(NetworkError::SessionDisconnected)
-                } else {
-                    Err(NetworkError::SessionSendError)
-                }
-            }
-        }
+        self.queue.push(pkt)
+    }
+
+    /// Serializes the message using the profile of this session and sends it.
+    pub fn send_msg<P: Protocol>(&mut self, msg: &P) -> Result<(), NetworkError> {
+        let pkt = msg.to_packet(self.profile)?;
+        self.send(pkt)
+    }
+
+    /// Queues the packet like `send`, but waits for room when the listener uses
+    /// `OverflowPolicy::Block`.
+    pub async fn send_async(&mut self, pkt: MuPacket) -> Result<(), NetworkError> {
+        self.queue.push_wait(pkt).await
+    }
+
+    /// Serializes the message using the profile of this session and sends it with `send_async`.
+    pub async fn send_msg_async<P: Protocol>(&mut self, msg: &P) -> Result<(), NetworkError> {
+        let pkt = msg.to_packet(self.profile)?;
+        self.send_async(pkt).await
+    }
+
+    /// Depth, high-water mark and drops of the outbound queue.
+    pub fn queue_stats(&self) -> QueueStats {
+        self.queue.stats()
     }
 }
 
@@ -123,31 +310,95 @@ impl PartialEq for SessionRef {
 }
 impl Eq for SessionRef {}
 
+
 type ClientsMap = Arc<Mutex<HashMap<u32, SessionRef>>>;
 
-pub struct Server {
-    handle: Handle,
-    evt_rx: Receiver<NetworkEvent>,
-    evt_tx: Sender<NetworkEvent>,
-    task: Arc<Mutex<Option<Task>>>,
+/// Endpoints of a connection started by `connect_to`, carried by its sessions to reconnect.
+#[derive(Clone)]
+struct Link {
+    endpoints: Arc<Vec<Endpoint>>,
+    //Set on sessions which got the link back after it was lost.
+    restored: bool,
+}
+
+/// State shared by the server and every task it spawns.
+#[derive(Clone)]
+struct ServerCtx {
+    evt_tx: mpsc::Sender<NetworkEvent>,
     clients: ClientsMap,
+    cipher: Option<Arc<SimpleModulus>>,
+    shutdown: CancellationToken,
+    //Writer tasks, waited on shutdown so queued packets still go out.
+    writers: TaskTracker,
 }
 
-impl<'a> Server {
-    pub fn new(handle: Handle) -> Server {
-        let (tx, rx): (Sender<NetworkEvent>, Receiver<NetworkEvent>) = mpsc::channel();
+impl ServerCtx {
+    /// Queues an event for the server. A full queue makes the session wait, so a slow handler
+    /// pushes back on the sockets instead of buffering without limit. Returns false once the
+    /// server was dropped.
+    async fn emit(&self, evt: NetworkEvent) -> bool {
+        //Only waits on the queue when it is full, which is rare while the handler keeps up.
+        match self.evt_tx.try_send(evt) {
+            Ok(()) => true,
+            Err(TrySendError::Full(evt)) => self.evt_tx.send(evt).await.is_ok(),
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+}
+
+enum ShutdownState {
+    Running(Pin<Box<WaitForCancellationFutureOwned>>),
+    //`ShuttingDown` was yielded, the handler is sending its last packets.
+    Announced,
+    Draining(BoxFuture<'static, ()>),
+    Done,
+}
+
+/// Every listener and outgoing connection runs on tasks of the tokio runtime, so servers must
+/// be set up from within one.
+pub struct Server {
+    evt_rx: mpsc::Receiver<NetworkEvent>,
+    ctx: ServerCtx,
+    state: ShutdownState,
+    drain_timeout: Duration,
+}
+
+impl Server {
+    pub fn new() -> Server {
+        let (tx, rx) = mpsc::channel(EVENT_QUEUE_LEN);
+        let shutdown = CancellationToken::new();
 
         Server {
-            handle: handle,
-            evt_tx: tx,
             evt_rx: rx,
-            task: Arc::new(Mutex::new(None)),
-            clients: Arc::new(Mutex::new(HashMap::new())),
+            state: ShutdownState::Running(Box::pin(shutdown.clone().cancelled_owned())),
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            ctx: ServerCtx {
+                evt_tx: tx,
+                clients: Arc::new(Mutex::new(HashMap::new())),
+                cipher: None,
+                shutdown,
+                writers: TaskTracker::new(),
+            },
         }
     }
 
+    /// Handle to stop this server, from any thread or task.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle::new(self.ctx.shutdown.clone())
+    }
+
+    /// Sets how long sessions may take to write their queued packets on shutdown.
+    pub fn set_drain_timeout(&mut self, timeout: Duration) {
+        self.drain_timeout = timeout;
+    }
+
+    /// Sets the SimpleModulus keys used on C3/C4 packets of every session started afterwards.
+    pub fn set_simple_modulus(&mut self, cipher: SimpleModulus) {
+        self.ctx.cipher = Some(Arc::new(cipher));
+    }
+
     pub fn send(&self, id: u32, pkt: MuPacket) -> Result<(), NetworkError> {
-        let mut map = self.clients.lock().unwrap();
+        let mut map = self.ctx.clients.lock().unwrap();
 
         if let Some(s_ref) = map.get_mut(&id) {
             s_ref.send(pkt)
@@ -156,223 +407,653 @@ impl<'a> Server {
         }
     }
 
-    pub fn connect_to(&mut self, listen_addr: &'a str, port: u16, kind: u8) -> Result<(), Error> {
-        let handle = self.handle.clone();
-        let addr = format!("{}:{}", listen_addr, port).parse()?;
+    pub fn connect_to(
+        &mut self,
+        listen_addr: &str,
+        port: u16,
+        kind: u8,
+        opts: SessionOptions,
+    ) -> Result<(), Error> {
+        let addr: SocketAddr = format!("{}:{}", listen_addr, port).parse()?;
+        self.connect_to_any(vec![Endpoint::Tcp(addr)], kind, opts)
+    }
 
-        let tx = self.evt_tx.clone();
-        let task = Arc::clone(&self.task);
-        let clients = Arc::clone(&self.clients);
-        let handle_cj = self.handle.clone();
+    /// Keeps a connection to the first endpoint that answers, trying them in order. A lost link
+    /// is retried from the first endpoint again, following the reconnect policy of `opts`.
+    pub fn connect_to_any(
+        &mut self,
+        endpoints: Vec<Endpoint>,
+        kind: u8,
+        opts: SessionOptions,
+    ) -> Result<(), Error> {
+        if endpoints.is_empty() {
+            return Err(NetworkError::InvalidAddress)?;
+        }
 
-        let ft = Server::try_connect(tx, kind, handle_cj, clients, addr, task).then(|_| Ok(()));
+        let link = Link {
+            endpoints: Arc::new(endpoints),
+            restored: false,
+        };
 
-        handle.spawn(ft);
+        tokio::spawn(Server::try_connect(self.ctx.clone(), kind, link, opts));
 
         Ok(())
     }
 
-    #[async]
+    //Boxed, since a closed session spawns a new attempt and the future would contain itself.
     fn try_connect(
-        tx: Sender<NetworkEvent>,
+        ctx: ServerCtx,
         kind: u8,
-        handle: Handle,
-        clients: ClientsMap,
-        addr: SocketAddr,
-        task: Arc<Mutex<Option<Task>>>,
-    ) -> Result<(), Error> {
-        loop {
-            if let Ok(stream) = await!(TcpStream::connect(&addr, &handle)) {
-                break await!(Server::handle_stream(
-                    stream,
-                    kind,
-                    tx,
-                    task,
-                    handle.clone(),
-                    clients,
-                    addr,
-                    true,
-                ));
+        link: Link,
+        opts: SessionOptions,
+    ) -> BoxFuture<'static, ()> {
+        async move {
+            let mut attempt = 0;
+
+            loop {
+                attempt += 1;
+
+                if link.restored && !ctx.emit(NetworkEvent::Reconnecting((kind, attempt))).await {
+                    return;
+                }
+
+                for endpoint in link.endpoints.iter() {
+                    let ended = match *endpoint {
+                        Endpoint::Tcp(addr) => {
+                            let connect = TcpStream::connect(addr);
+
+                            match ctx.shutdown.run_until_cancelled(connect).await {
+                                Some(Ok(stream)) => {
+                                    Server::start_link(&ctx, stream, kind, &link, &opts, endpoint)
+                                        .await
+                                }
+                                Some(Err(_)) => false,
+                                None => return,
+                            }
+                        }
+                        #[cfg(unix)]
+                        Endpoint::Unix(ref path) => {
+                            let connect = UnixStream::connect(path);
+
+                            match ctx.shutdown.run_until_cancelled(connect).await {
+                                Some(Ok(stream)) => {
+                                    Server::start_link(&ctx, stream, kind, &link, &opts, endpoint)
+                                        .await
+                                }
+                                Some(Err(_)) => false,
+                                None => return,
+                            }
+                        }
+                    };
+
+                    if ended {
+                        return;
+                    }
+                }
+
+                if opts.reconnect.gives_up(attempt) {
+                    println!("Giving up on {:?} after {} attempts.", link.endpoints, attempt);
+                    ctx.emit(NetworkEvent::LinkFailed((kind, attempt))).await;
+                    return;
+                }
+
+                //Only the first failure is reported, retries are told through `Reconnecting`.
+                if attempt == 1 {
+                    println!("Failed to connect to {:?}. Retrying...", link.endpoints);
+                }
+
+                let delay = sleep(opts.reconnect.delay(attempt));
+                if ctx.shutdown.run_until_cancelled(delay).await.is_none() {
+                    return;
+                }
             }
-            println!("Failed to connect to {:?}. Retrying...", addr);
-            await!(Timer::default().sleep(Duration::from_secs(1)))?;
         }
+        .boxed()
     }
 
+    /// Runs the TLS handshake of links which require it, then the session until it ends.
+    /// Returns false if the handshake failed, so the next endpoint is tried.
+    async fn start_link<S>(
+        ctx: &ServerCtx,
+        stream: S,
+        kind: u8,
+        link: &Link,
+        opts: &SessionOptions,
+        endpoint: &Endpoint,
+    ) -> bool
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let (ctx, opts, endpoint) = (ctx.clone(), opts.clone(), endpoint.clone());
+        let link = Some(link.clone());
 
-    pub fn start_tcp(&mut self, listen_addr: &'a str, port: u16, kind: u8) -> Result<(), Error> {
-        let handle = self.handle.clone();
-        let addr = format!("{}:{}", listen_addr, port).parse()?;
+        let tls = match opts.tls {
+            Some(ref tls) => tls.clone(),
+            None => {
+                Server::handle_stream(ctx, stream, kind, endpoint, None, link, opts).await;
+                return true;
+            }
+        };
+
+        let handshake = tls.connector().connect(tls.server_name(&endpoint), stream);
+
+        match timeout(TLS_HANDSHAKE_TIMEOUT, handshake).await {
+            Ok(Ok(stream)) => {
+                Server::handle_stream(ctx, stream, kind, endpoint, None, link, opts).await;
+                return true;
+            }
+            Ok(Err(e)) => println!("TLS handshake with {} failed: {}", endpoint, e),
+            Err(_) => println!("TLS handshake with {} timed out.", endpoint),
+        }
+
+        false
+    }
+
+    pub fn start_tcp(
+        &mut self,
+        listen_addr: &str,
+        port: u16,
+        kind: u8,
+        opts: SessionOptions,
+    ) -> Result<(), Error> {
+        let addr: SocketAddr = format!("{}:{}", listen_addr, port).parse()?;
 
         println!("Binding TCP on {:?}", addr);
 
-        let listener = match TcpListener::bind(&addr, &handle) {
+        //Bound right away, so a busy port is reported to the caller instead of the runtime.
+        let listener = match net::TcpListener::bind(addr)
+            .and_then(|l| l.set_nonblocking(true).map(|_| l))
+            .and_then(TcpListener::from_std)
+        {
             Err(_) => return Err(NetworkError::TcpBindError)?,
             Ok(r) => r,
         };
 
-        handle.spawn(
-            Server::handle_listener(
-                listener,
-                self.evt_tx.clone(),
-                Arc::clone(&self.task),
-                self.handle.clone(),
-                Arc::clone(&self.clients),
-                kind,
-            ).then(|_| Ok(())),
-        );
+        tokio::spawn(Server::handle_listener(self.ctx.clone(), listener, kind, opts));
 
         Ok(())
     }
 
-    #[async]
-    fn handle_listener(
+    async fn handle_listener(
+        ctx: ServerCtx,
         listener: TcpListener,
-        tx: Sender<NetworkEvent>,
-        task_shr: Arc<Mutex<Option<Task>>>,
-        handle: Handle,
-        clients: ClientsMap,
         kind: u8,
-    ) -> Result<(), Error> {
-        #[async]
-        for (stream, peer_addr) in listener.incoming() {
-            handle.spawn(
-                Server::handle_stream(
-                    stream,
-                    kind,
-                    tx.clone(),
-                    Arc::clone(&task_shr),
-                    handle.clone(),
-                    Arc::clone(&clients),
-                    peer_addr,
-                    false,
-                ).then(|_| Ok(())),
-            );
+        opts: SessionOptions,
+    ) {
+        let limiter = Arc::new(Limiter::new(opts.limits.clone()));
+
+        //Dropping the listener on shutdown refuses new connections right away.
+        while let Some(res) = ctx.shutdown.run_until_cancelled(listener.accept()).await {
+            match res {
+                Ok((stream, peer_addr)) => {
+                    if let Some(ref filter) = opts.filter {
+                        if !filter.allows(peer_addr.ip()) {
+                            println!("Rejected connection from {} on kind {}.", peer_addr, kind);
+                            continue;
+                        }
+                    }
+
+                    //Refused connections are dropped silently, floods would flood the log too.
+                    let permit = match limiter.admit(peer_addr.ip()) {
+                        Ok(permit) => permit,
+                        Err(_) => continue,
+                    };
+
+                    tokio::spawn(Server::accept_stream(
+                        ctx.clone(),
+                        stream,
+                        kind,
+                        Endpoint::Tcp(peer_addr),
+                        Some(permit),
+                        opts.clone(),
+                    ));
+                }
+                Err(e) => println!("Failed to accept TCP connection: {}", e),
+            }
         }
+    }
+
+    /// Binds a UDP socket, which emits `NetworkEvent::Datagram` for every packet it receives.
+    /// Address filters apply to every datagram, while connection caps and packet rates don't, as
+    /// there is no connection to refuse. Binding on port 0 picks a free port, for sockets which
+    /// only send.
+    pub fn start_udp(
+        &mut self,
+        listen_addr: &str,
+        port: u16,
+        kind: u8,
+        opts: SessionOptions,
+    ) -> Result<UdpSocketRef, Error> {
+        let addr: SocketAddr = format!("{}:{}", listen_addr, port).parse()?;
+
+        println!("Binding UDP on {:?}", addr);
+
+        let bound = net::UdpSocket::bind(addr).and_then(|s| s.set_nonblocking(true).map(|_| s));
+
+        let socket = match bound {
+            Err(_) => return Err(NetworkError::UdpBindError)?,
+            Ok(s) => s,
+        };
+
+        let socket = UdpSocketRef::new(socket, kind, self.ctx.cipher.clone(), opts.clone())?;
+        tokio::spawn(Server::handle_udp_socket(self.ctx.clone(), socket.clone(), opts));
+
+        Ok(socket)
+    }
 
+    async fn handle_udp_socket(ctx: ServerCtx, socket: UdpSocketRef, opts: SessionOptions) {
+        let mut buf = vec![0u8; MAX_DATAGRAM_LEN];
+
+        while let Some(res) = ctx.shutdown.run_until_cancelled(socket.recv_from(&mut buf)).await {
+            let (len, from) = match res {
+                Ok(res) => res,
+                Err(e) => {
+                    println!("Failed to receive UDP datagram: {}", e);
+                    continue;
+                }
+            };
+
+            //Sources are trivial to spoof, so rejected datagrams aren't worth a log line.
+            if opts.filter.as_ref().is_some_and(|f| !f.allows(from.ip())) {
+                continue;
+            }
+
+            let pkts = match socket.decode(&buf[..len]) {
+                Ok(pkts) => pkts,
+                Err(e) => {
+                    println!("Invalid datagram from {}: {}", from, e);
+                    continue;
+                }
+            };
+
+            for pkt in pkts {
+                if !ctx.emit(NetworkEvent::Datagram((socket.clone(), from, pkt))).await {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Listens on a Unix socket, for servers on the same host. Peers are trusted by the socket
+    /// file permissions, so address filters and connection caps don't apply.
+    #[cfg(unix)]
+    pub fn start_unix(&mut self, path: &str, kind: u8, opts: SessionOptions) -> Result<(), Error> {
+        println!("Binding Unix socket on {}", path);
+
+        //A socket left by a previous run makes bind fail, unless something still listens on it.
+        if let Ok(meta) = fs::symlink_metadata(path) {
+            if meta.file_type().is_socket() && unix_net::UnixStream::connect(path).is_err() {
+                fs::remove_file(path).ok();
+            }
+        }
+
+        let listener = match UnixListener::bind(path) {
+            Err(_) => return Err(NetworkError::UnixBindError)?,
+            Ok(l) => l,
+        };
+
+        let path = PathBuf::from(path);
+        tokio::spawn(Server::handle_unix_listener(self.ctx.clone(), listener, path, kind, opts));
 
         Ok(())
     }
 
-    #[async]
-    fn handle_stream(
-        stream: TcpStream,
+    #[cfg(unix)]
+    async fn handle_unix_listener(
+        ctx: ServerCtx,
+        listener: UnixListener,
+        path: PathBuf,
         kind: u8,
-        tx: Sender<NetworkEvent>,
-        task_shr: Arc<Mutex<Option<Task>>>,
-        handle: Handle,
-        clients: ClientsMap,
-        addr: SocketAddr,
-        reconnect: bool,
-    ) -> Result<(), Error> {
+        opts: SessionOptions,
+    ) {
+        while let Some(res) = ctx.shutdown.run_until_cancelled(listener.accept()).await {
+            match res {
+                //Peers rarely bind a path of their own, so sessions are known by the listener's.
+                Ok((stream, _)) => {
+                    tokio::spawn(Server::accept_stream(
+                        ctx.clone(),
+                        stream,
+                        kind,
+                        Endpoint::Unix(path.clone()),
+                        None,
+                        opts.clone(),
+                    ));
+                }
+                Err(e) => println!("Failed to accept Unix connection: {}", e),
+            }
+        }
+
+        fs::remove_file(&path).ok();
+    }
+
+    /// Runs the TLS handshake of listeners which require it, then the session.
+    async fn accept_stream<S>(
+        ctx: ServerCtx,
+        stream: S,
+        kind: u8,
+        endpoint: Endpoint,
+        permit: Option<ConnectionPermit>,
+        opts: SessionOptions,
+    ) where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let tls = match opts.tls {
+            Some(ref tls) => tls.clone(),
+            None => {
+                return Server::handle_stream(ctx, stream, kind, endpoint, permit, None, opts).await
+            }
+        };
+
+        match timeout(TLS_HANDSHAKE_TIMEOUT, tls.acceptor().accept(stream)).await {
+            Ok(Ok(stream)) => {
+                Server::handle_stream(ctx, stream, kind, endpoint, permit, None, opts).await
+            }
+            Ok(Err(e)) => println!("TLS handshake with {} failed: {}", endpoint, e),
+            Err(_) => println!("TLS handshake with {} timed out.", endpoint),
+        }
+    }
+
+    async fn handle_stream<S>(
+        ctx: ServerCtx,
+        stream: S,
+        kind: u8,
+        endpoint: Endpoint,
+        permit: Option<ConnectionPermit>,
+        link: Option<Link>,
+        opts: SessionOptions,
+    ) where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
         let id = SESSION_ID_COUNTER.fetch_add(1, Ordering::Relaxed) as u32;
-        let (ssn_reader, ssn_writer) = TcpSession::new_pair(stream, id);
-        let (s_tx, s_rx): (f_mpsc::Sender<MuPacket>, f_mpsc::Receiver<MuPacket>) =
-            f_mpsc::channel(100);
+        let (ssn_reader, ssn_writer) =
+            tcp_session::new_pair(stream, id, kind, ctx.cipher.clone(), &opts);
+        let queue = Arc::new(SessionQueue::new(opts.queue_len, opts.overflow));
 
-        let s_ref = SessionRef::new(id, kind, s_tx.clone(), addr);
+        let s_ref = SessionRef::new(id, kind, opts.profile, Arc::clone(&queue), endpoint);
 
         {
-            let mut map = clients.lock().unwrap();
+            let mut map = ctx.clients.lock().unwrap();
             map.insert(id, s_ref.clone());
+
+            //Checked under the lock, so a shutdown either sees this session or this sees it.
+            if ctx.shutdown.is_cancelled() {
+                queue.close();
+            }
+        }
+
+        if !ctx.emit(NetworkEvent::ClientConnected(s_ref.clone())).await {
+            println!("Failed to send Connected event.");
+            return;
         }
 
-        let evt = NetworkEvent::ClientConnected(s_ref.clone());
+        if link.as_ref().is_some_and(|l| l.restored)
+            && !ctx.emit(NetworkEvent::LinkRestored(s_ref.clone())).await
+        {
+            return;
+        }
+
+        ctx.writers.spawn(Server::write_tcp_session(ssn_writer, queue, Arc::clone(&s_ref.meta)));
+
+        if let Some(period) = opts.heartbeat {
+            tokio::spawn(Server::send_heartbeat(s_ref.clone(), period));
+        }
+
+        Server::handle_tcp_session(ctx, ssn_reader, s_ref, permit, link, opts).await
+    }
+
+    async fn write_tcp_session<S>(
+        mut ssn_writer: TcpSessionWriter<WriteHalf<S>>,
+        queue: Arc<SessionQueue>,
+        meta: Arc<SessionMeta>,
+    ) where
+        S: AsyncWrite,
+    {
+        let res = Server::write_queued(&mut ssn_writer, &queue, &meta).await;
+
+        //Nothing gets written from now on, so senders must stop queueing.
+        queue.shutdown();
 
-        if tx.send(evt).is_ok() {
-            if let Some(ref t) = *task_shr.lock().unwrap() {
-                t.notify();
+        if res.is_ok() {
+            ssn_writer.close().await.ok();
+        }
+    }
+
+    async fn write_queued<S>(
+        ssn_writer: &mut TcpSessionWriter<WriteHalf<S>>,
+        queue: &SessionQueue,
+        meta: &SessionMeta,
+    ) -> Result<(), Error>
+    where
+        S: AsyncWrite,
+    {
+        //Packets only count as sent once a flush wrote them to the socket.
+        let (mut packets, mut bytes) = (0, 0);
+
+        while let Some(pkt) = queue.pop().await {
+            //An empty packet asks to close the session, after everything sent before it.
+            if pkt.is_empty() {
+                break;
+            }
+
+            let len = pkt.len();
+            ssn_writer.feed(pkt).await?;
+            packets += 1;
+            bytes += len;
+
+            //Packets queued meanwhile go out together on the next flush.
+            if queue.is_empty() {
+                ssn_writer.flush().await?;
+                meta.sent(packets, bytes);
+                (packets, bytes) = (0, 0);
             }
-        } else {
-            println!("Failed to send Connected event.");
-            return Ok(());
         }
 
-        let ft = ssn_writer
-            .send_all(s_rx.map_err(|_| TcpSessionError::TcpStreamWrite))
-            .then(|_| Ok(()));
-
-        handle.spawn(ft);
-
-        await!(Server::handle_tcp_session(
-            ssn_reader,
-            tx.clone(),
-            Arc::clone(&task_shr),
-            Arc::clone(&clients),
-            s_ref.clone(),
-            reconnect,
-            handle.clone(),
-        ))
-    }
-
-    #[async]
-    fn handle_tcp_session(
-        ssn_reader: TcpSessionReader<ReadHalf<TcpStream>>,
-        tx: Sender<NetworkEvent>,
-        task_shr: Arc<Mutex<Option<Task>>>,
-        clients: ClientsMap,
-        s_ref: SessionRef,
-        reconnect: bool,
-        handle: Handle,
-    ) -> Result<(), Error> {
-        let task_shr_cs = Arc::clone(&task_shr);
-        let s_ref_cj = s_ref.clone();
+        ssn_writer.flush().await?;
+        meta.sent(packets, bytes);
+
+        Ok(())
+    }
 
-        #[async]
-        for packet in ssn_reader {
-            let task = task_shr_cs.lock().unwrap();
+    async fn handle_tcp_session<S>(
+        ctx: ServerCtx,
+        ssn_reader: TcpSessionReader<ReadHalf<S>>,
+        s_ref: SessionRef,
+        permit: Option<ConnectionPermit>,
+        link: Option<Link>,
+        opts: SessionOptions,
+    ) where
+        S: AsyncRead,
+    {
+        let res = Server::read_tcp_session(&ctx, ssn_reader, &s_ref, &opts).await;
 
-            let evt = NetworkEvent::ClientPacket((s_ref_cj.clone(), packet));
+        //A malformed packet only costs the offending session, never the whole runtime.
+        let reason = match res {
+            Ok(reason) => reason,
+            Err(e) => {
+                println!("Closing session {}: {}", s_ref.id, e);
+                DisconnectReason::Error
+            }
+        };
 
-            if tx.send(evt).is_ok() {
-                if let Some(ref t) = *task {
-                    t.notify();
-                }
-            } else {
-                println!("Failed to send Packet event.");
-                return Ok(());
+        if reason == DisconnectReason::Flood {
+            if let Some(ref permit) = permit {
+                permit.ban();
             }
         }
 
+        //The address may connect again as soon as this session is gone.
+        drop(permit);
+
+        //Also stops the writer half, in case the peer is still connected.
+        s_ref.clone().close().ok();
+
         let session_id = s_ref.id;
 
         {
-            let mut map = clients.lock().unwrap();
+            let mut map = ctx.clients.lock().unwrap();
             map.remove(&session_id);
         }
 
-        let evt = NetworkEvent::ClientDisconnected((session_id, s_ref.kind));
+        let evt = NetworkEvent::ClientDisconnected((session_id, s_ref.kind, reason));
 
-        if tx.send(evt).is_ok() {
-            if let Some(ref t) = *task_shr.lock().unwrap() {
-                t.notify();
-            }
-        } else {
+        if !ctx.emit(evt).await {
             println!("Failed to send Disconnected event.");
-            return Ok(());
+            return;
         }
 
-        if reconnect {
-            let handle_cj = handle.clone();
-            let ft = Server::try_connect(tx, s_ref.kind, handle_cj, clients, s_ref.addr, task_shr)
-                .then(|_| Ok(()));
-            handle.spawn(ft);
+        let mut link = match link {
+            Some(link) if !ctx.shutdown.is_cancelled() => link,
+            _ => return,
+        };
+
+        if !ctx.emit(NetworkEvent::LinkLost((s_ref.kind, s_ref.endpoint().clone()))).await {
+            return;
         }
 
-        Ok(())
+        link.restored = true;
+        tokio::spawn(Server::try_connect(ctx, s_ref.kind, link, opts));
+    }
+
+    async fn read_tcp_session<S>(
+        ctx: &ServerCtx,
+        mut ssn_reader: TcpSessionReader<ReadHalf<S>>,
+        s_ref: &SessionRef,
+        opts: &SessionOptions,
+    ) -> Result<DisconnectReason, Error>
+    where
+        S: AsyncRead,
+    {
+        let read_timeout = opts.read_timeout();
+        let mut rate = RateLimiter::new(&opts.limits);
+
+        loop {
+            let next = match read_timeout {
+                Some(t) => match timeout(t, ssn_reader.next()).await {
+                    Ok(next) => next,
+                    Err(_) => return Ok(DisconnectReason::Idle),
+                },
+                None => ssn_reader.next().await,
+            };
+
+            let packet = match next {
+                Some(packet) => packet?,
+                None => return Ok(DisconnectReason::Closed),
+            };
+
+            if !rate.allow(packet.len()) {
+                return Ok(DisconnectReason::Flood);
+            }
+
+            s_ref.meta.received(packet.len());
+
+            if opts.heartbeat.is_some() && Server::handle_heartbeat(s_ref, &packet) {
+                continue;
+            }
+
+            let evt = NetworkEvent::ClientPacket((s_ref.clone(), packet));
+
+            if !ctx.emit(evt).await {
+                println!("Failed to send Packet event.");
+                return Ok(DisconnectReason::Closed);
+            }
+        }
+    }
+
+    /// Answers pings and swallows pongs, which only need to reset the idle timeout.
+    /// Returns false for any other packet.
+    fn handle_heartbeat(s_ref: &SessionRef, pkt: &MuPacket) -> bool {
+        //Every packet goes through here, so only pings get their body parsed.
+        match ProtoMsg::lookup(pkt.kind(), pkt.code, pkt.sub_code, s_ref.profile) {
+            Some(ProtoMsg::Ping) => match Ping::parse(pkt.data(), s_ref.profile) {
+                Ok(ping) => {
+                    if let Ok(pong) = (Pong { seq: ping.seq }).to_packet(s_ref.profile) {
+                        s_ref.queue.try_push(pong).ok();
+                    }
+                    true
+                }
+                Err(_) => false,
+            },
+            Some(ProtoMsg::Pong) => true,
+            _ => false,
+        }
+    }
+
+    async fn send_heartbeat(s_ref: SessionRef, period: Duration) {
+        let mut ticks = interval_at(Instant::now() + period, period);
+        let mut seq = 0u32;
+
+        loop {
+            ticks.tick().await;
+
+            let ping = Ping { seq };
+            seq = seq.wrapping_add(1);
+
+            //A full queue already has traffic on the way, so the ping is simply skipped.
+            let res = match ping.to_packet(s_ref.profile) {
+                Ok(pkt) => s_ref.queue.try_push(pkt),
+                Err(err) => Err(err.into()),
+            };
+
+            if let Err(NetworkError::SessionDisconnected) = res {
+                break;
+            }
+        }
+    }
+
+    /// Closes every session and waits for their queued packets to be written, up to `deadline`.
+    async fn drain(ctx: ServerCtx, deadline: Duration) {
+        let sessions: Vec<SessionRef> = {
+            let map = ctx.clients.lock().unwrap();
+            map.values().cloned().collect()
+        };
+
+        for mut s_ref in sessions {
+            s_ref.close().ok();
+        }
+
+        ctx.writers.close();
+
+        if timeout(deadline, ctx.writers.wait()).await.is_err() {
+            println!("Drain timeout reached, {} sessions still writing.", ctx.writers.len());
+        }
+    }
+}
+
+impl Default for Server {
+    fn default() -> Server {
+        Server::new()
     }
 }
 
 impl Stream for Server {
     type Item = NetworkEvent;
-    type Error = Error;
-    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        *self.task.lock().unwrap() = Some(task::current());
-
-        match self.evt_rx.try_recv() {
-            Err(mpsc::TryRecvError::Empty) => Ok(Async::NotReady),
-            Err(mpsc::TryRecvError::Disconnected) => Ok(Async::Ready(None)),
-            Ok(evt) => Ok(Async::Ready(Some(evt))),
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+
+        match this.state {
+            ShutdownState::Running(ref mut shutdown) => {
+                if shutdown.as_mut().poll(cx).is_ready() {
+                    this.state = ShutdownState::Announced;
+                    return Poll::Ready(Some(NetworkEvent::ShuttingDown));
+                }
+            }
+            //Polled again, so the handler is done with `ShuttingDown`.
+            ShutdownState::Announced => {
+                let drain = Server::drain(this.ctx.clone(), this.drain_timeout).boxed();
+                this.state = ShutdownState::Draining(drain);
+                return Pin::new(this).poll_next(cx);
+            }
+            ShutdownState::Draining(ref mut drain) => {
+                if drain.as_mut().poll(cx).is_ready() {
+                    this.state = ShutdownState::Done;
+                    return Poll::Ready(None);
+                }
+            }
+            ShutdownState::Done => return Poll::Ready(None),
         }
+
+        //Sessions closing while draining still report their disconnection.
+        this.evt_rx.poll_recv(cx)
     }
 }
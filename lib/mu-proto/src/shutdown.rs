@@ -0,0 +1,60 @@
+use futures::future;
+
+use tokio::signal;
+use tokio_util::sync::CancellationToken;
+
+/// Stops a `Server`: listeners stop accepting, outgoing connections stop retrying and the server
+/// yields `NetworkEvent::ShuttingDown`. Once the handler polls it again, every session is closed
+/// and the server ends after the queued packets are written or the drain timeout passes.
+#[derive(Clone, Debug)]
+pub struct ShutdownHandle {
+    token: CancellationToken,
+}
+
+impl ShutdownHandle {
+    pub(crate) fn new(token: CancellationToken) -> ShutdownHandle {
+        ShutdownHandle { token }
+    }
+
+    pub fn shutdown(&self) {
+        self.token.cancel();
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Shuts down on SIGINT or SIGTERM (Ctrl-C only, outside of unix). Must be called from
+    /// within the tokio runtime.
+    pub fn on_signals(&self) {
+        let handle = self.clone();
+
+        tokio::spawn(async move {
+            match wait_signal().await {
+                Ok(()) => {
+                    println!("Shutdown requested.");
+                    handle.shutdown();
+                }
+                Err(e) => println!("Failed to listen for shutdown signals: {}", e),
+            }
+        });
+    }
+}
+
+#[cfg(unix)]
+async fn wait_signal() -> Result<(), std::io::Error> {
+    let mut term = signal::unix::signal(signal::unix::SignalKind::terminate())?;
+
+    let ctrl_c = Box::pin(signal::ctrl_c());
+    let res = match future::select(ctrl_c, Box::pin(term.recv())).await {
+        future::Either::Left((res, _)) => res,
+        future::Either::Right(_) => Ok(()),
+    };
+
+    res
+}
+
+#[cfg(not(unix))]
+async fn wait_signal() -> Result<(), std::io::Error> {
+    signal::ctrl_c().await
+}
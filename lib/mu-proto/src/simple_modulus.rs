@@ -0,0 +1,367 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use failure::Error;
+
+const KEY_FILE_HEADER: u16 = 0x1112;
+const KEY_FILE_HEADER_LEN: usize = 8; //u16 header, 2 padding bytes and u32 file size
+const KEY_FILE_LEN: usize = KEY_FILE_HEADER_LEN + 3 * 16; //Modulus, key and xor key.
+const KEY_FILE_XOR: [u32; 4] = [0x3F08_A79B, 0xE25C_C287, 0x93D2_7AB9, 0x20DE_A7BF];
+
+const DECRYPTED_BLOCK_LEN: usize = 8;
+const ENCRYPTED_BLOCK_LEN: usize = 11;
+
+#[derive(Debug, Fail)]
+pub enum SimpleModulusError {
+    #[fail(display = "Failed to read SimpleModulus key file.")]
+    KeyFileRead,
+    #[fail(display = "Invalid SimpleModulus key file.")]
+    InvalidKeyFile,
+    #[fail(display = "Encrypted data isn't a multiple of the block size: {}", _0)]
+    InvalidLength(usize),
+    #[fail(display = "Encrypted block checksum mismatch.")]
+    InvalidChecksum,
+    #[fail(display = "Unexpected packet serial. Expected {:02X}, received {:02X}", _0, _1)]
+    InvalidSerial(u8, u8),
+    #[fail(display = "Encrypted packet doesn't fit on its header: {} bytes", _0)]
+    FrameTooLarge(usize),
+}
+
+/// One direction of the SimpleModulus cipher, as stored on Enc*.dat / Dec*.dat files.
+/// `key` is the encryption key on Enc files and the decryption key on Dec files.
+#[derive(Debug, Clone)]
+pub struct SimpleModulusKeys {
+    pub modulus: [u32; 4],
+    pub key: [u32; 4],
+    pub xor: [u32; 4],
+}
+
+impl SimpleModulusKeys {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<SimpleModulusKeys, Error> {
+        let mut buf = vec![];
+
+        File::open(path)
+            .and_then(|mut f| f.read_to_end(&mut buf))
+            .map_err(|_| SimpleModulusError::KeyFileRead)?;
+
+        Ok(SimpleModulusKeys::parse(&buf)?)
+    }
+
+    pub fn parse(buf: &[u8]) -> Result<SimpleModulusKeys, SimpleModulusError> {
+        if buf.len() < KEY_FILE_LEN {
+            return Err(SimpleModulusError::InvalidKeyFile);
+        }
+
+        let header = u16::from(buf[0]) | u16::from(buf[1]) << 8;
+        let size = read_u32_le(&buf[4..8]) as usize;
+
+        if header != KEY_FILE_HEADER || size != KEY_FILE_LEN {
+            return Err(SimpleModulusError::InvalidKeyFile);
+        }
+
+        let read_key = |n: usize| {
+            let mut key = [0u32; 4];
+            for (i, k) in key.iter_mut().enumerate() {
+                let idx = KEY_FILE_HEADER_LEN + n * 16 + i * 4;
+                *k = read_u32_le(&buf[idx..idx + 4]) ^ KEY_FILE_XOR[i];
+            }
+            key
+        };
+
+        let keys = SimpleModulusKeys {
+            modulus: read_key(0),
+            key: read_key(1),
+            xor: read_key(2),
+        };
+
+        //Every block is reduced by the modulus, a zero one would divide by zero.
+        if keys.modulus.contains(&0) {
+            return Err(SimpleModulusError::InvalidKeyFile);
+        }
+
+        Ok(keys)
+    }
+
+    /// Encrypts `src` in blocks of 8 bytes, each one becoming 11 bytes.
+    pub fn encrypt(&self, src: &[u8]) -> Vec<u8> {
+        let blocks = src.len().div_ceil(DECRYPTED_BLOCK_LEN);
+        let mut dst = vec![0; blocks * ENCRYPTED_BLOCK_LEN];
+
+        for (i, chunk) in src.chunks(DECRYPTED_BLOCK_LEN).enumerate() {
+            let out = &mut dst[i * ENCRYPTED_BLOCK_LEN..(i + 1) * ENCRYPTED_BLOCK_LEN];
+            self.encrypt_block(chunk, out);
+        }
+
+        dst
+    }
+
+    /// Decrypts blocks of 11 bytes, trimming each one to the real length stored on it.
+    pub fn decrypt(&self, src: &[u8]) -> Result<Vec<u8>, SimpleModulusError> {
+        if !src.len().is_multiple_of(ENCRYPTED_BLOCK_LEN) {
+            return Err(SimpleModulusError::InvalidLength(src.len()));
+        }
+
+        let mut dst = Vec::with_capacity(src.len() / ENCRYPTED_BLOCK_LEN * DECRYPTED_BLOCK_LEN);
+
+        for chunk in src.chunks(ENCRYPTED_BLOCK_LEN) {
+            let mut block = [0u8; DECRYPTED_BLOCK_LEN];
+            let len = self.decrypt_block(chunk, &mut block)?;
+            dst.extend_from_slice(&block[..len]);
+        }
+
+        Ok(dst)
+    }
+
+    fn encrypt_block(&self, src: &[u8], dst: &mut [u8]) {
+        let mut input = [0u8; DECRYPTED_BLOCK_LEN];
+        input[..src.len()].copy_from_slice(src);
+
+        let mut enc = [0u32; 4];
+        let mut prev = 0u32;
+
+        for i in 0..4 {
+            let word = u32::from(input[i * 2]) | u32::from(input[i * 2 + 1]) << 8;
+            enc[i] = (self.xor[i] ^ word ^ prev).wrapping_mul(self.key[i]) % self.modulus[i];
+            prev = enc[i] & 0xFFFF;
+        }
+
+        for i in 0..3 {
+            enc[i] = enc[i] ^ self.xor[i] ^ (enc[i + 1] & 0xFFFF);
+        }
+
+        let mut writer = BitWriter::new(dst);
+        for val in &enc {
+            writer.write(*val & 0xFF, 8);
+            writer.write((*val >> 8) & 0xFF, 8);
+            writer.write((*val >> 16) & 0x03, 2);
+        }
+
+        let checksum = input.iter().fold(0xF8, |acc, b| acc ^ b);
+        writer.write(u32::from(checksum ^ src.len() as u8 ^ 0x3D), 8);
+        writer.write(u32::from(checksum), 8);
+    }
+
+    fn decrypt_block(&self, src: &[u8], dst: &mut [u8]) -> Result<usize, SimpleModulusError> {
+        let mut reader = BitReader::new(src);
+        let mut dec = [0u32; 4];
+
+        for val in &mut dec {
+            *val = reader.read(8) | reader.read(8) << 8 | reader.read(2) << 16;
+        }
+
+        for i in (0..3).rev() {
+            dec[i] = dec[i] ^ self.xor[i] ^ (dec[i + 1] & 0xFFFF);
+        }
+
+        let mut prev = 0u32;
+        for i in 0..4 {
+            let word = (self.key[i].wrapping_mul(dec[i]) % self.modulus[i]) ^ self.xor[i] ^ prev;
+            prev = dec[i] & 0xFFFF;
+
+            dst[i * 2] = word as u8;
+            dst[i * 2 + 1] = (word >> 8) as u8;
+        }
+
+        let len = reader.read(8) as u8;
+        let checksum = reader.read(8) as u8;
+
+        if dst.iter().fold(0xF8, |acc, b| acc ^ b) != checksum {
+            return Err(SimpleModulusError::InvalidChecksum);
+        }
+
+        let len = (len ^ checksum ^ 0x3D) as usize;
+        if len > DECRYPTED_BLOCK_LEN {
+            return Err(SimpleModulusError::InvalidChecksum);
+        }
+
+        Ok(len)
+    }
+}
+
+/// Holds both directions of the cipher: `enc` for what we send and `dec` for what we receive.
+#[derive(Debug, Clone)]
+pub struct SimpleModulus {
+    pub enc: SimpleModulusKeys,
+    pub dec: SimpleModulusKeys,
+}
+
+impl SimpleModulus {
+    pub fn new(enc: SimpleModulusKeys, dec: SimpleModulusKeys) -> SimpleModulus {
+        SimpleModulus { enc, dec }
+    }
+
+    pub fn load<P: AsRef<Path>>(enc_path: P, dec_path: P) -> Result<SimpleModulus, Error> {
+        Ok(SimpleModulus::new(
+            SimpleModulusKeys::load(enc_path)?,
+            SimpleModulusKeys::load(dec_path)?,
+        ))
+    }
+
+    /// Turns a plain C3/C4 frame into its encrypted wire form. The packet serial is
+    /// encrypted together with the code and body, right after the size field.
+    pub fn encrypt_frame(&self, frame: &[u8], serial: u8) -> Result<Vec<u8>, SimpleModulusError> {
+        let hdr_len = match frame.first() {
+            Some(kind) => size_field_end(*kind),
+            None => return Err(SimpleModulusError::InvalidLength(0)),
+        };
+
+        if frame.len() < hdr_len {
+            return Err(SimpleModulusError::InvalidLength(frame.len()));
+        }
+
+        let mut plain = Vec::with_capacity(frame.len() - hdr_len + 1);
+        plain.push(serial);
+        plain.extend_from_slice(&frame[hdr_len..]);
+
+        let enc = self.enc.encrypt(&plain);
+        let len = hdr_len + enc.len();
+
+        let mut out = Vec::with_capacity(len);
+        out.push(frame[0]);
+
+        if hdr_len == 2 {
+            if len > 0xFF {
+                return Err(SimpleModulusError::FrameTooLarge(len));
+            }
+            out.push(len as u8);
+        } else {
+            if len > 0xFFFF {
+                return Err(SimpleModulusError::FrameTooLarge(len));
+            }
+            out.push((len >> 8) as u8);
+            out.push(len as u8);
+        }
+
+        out.extend_from_slice(&enc);
+        Ok(out)
+    }
+
+    /// Turns an encrypted C3/C4 frame back into a plain one, returning it along with the
+    /// packet serial that was sent inside of it.
+    pub fn decrypt_frame(&self, frame: &[u8]) -> Result<(Vec<u8>, u8), SimpleModulusError> {
+        let hdr_len = match frame.first() {
+            Some(kind) => size_field_end(*kind),
+            None => return Err(SimpleModulusError::InvalidLength(0)),
+        };
+
+        if frame.len() < hdr_len {
+            return Err(SimpleModulusError::InvalidLength(frame.len()));
+        }
+
+        let dec = self.dec.decrypt(&frame[hdr_len..])?;
+
+        if dec.len() < 2 {
+            //Not even the serial and the code are there.
+            return Err(SimpleModulusError::InvalidLength(dec.len()));
+        }
+
+        let len = hdr_len + dec.len() - 1;
+
+        let mut out = Vec::with_capacity(len);
+        out.push(frame[0]);
+
+        if hdr_len == 2 {
+            out.push(len as u8);
+        } else {
+            out.push((len >> 8) as u8);
+            out.push(len as u8);
+        }
+
+        out.extend_from_slice(&dec[1..]);
+        Ok((out, dec[0]))
+    }
+}
+
+/// Counts C3/C4 packets on one direction of a session. Both peers increment it on every
+/// encrypted packet, so a mismatch means a packet was lost, replayed or forged.
+#[derive(Clone, Debug, Default)]
+pub struct PacketSerial {
+    next: u8,
+}
+
+impl PacketSerial {
+    pub fn new() -> PacketSerial {
+        PacketSerial { next: 0 }
+    }
+
+    pub fn current(&self) -> u8 {
+        self.next
+    }
+
+    pub fn advance(&mut self) {
+        self.next = self.next.wrapping_add(1);
+    }
+
+    pub fn take(&mut self) -> u8 {
+        let serial = self.current();
+        self.advance();
+        serial
+    }
+
+    pub fn check(&mut self, serial: u8) -> Result<(), SimpleModulusError> {
+        let expected = self.take();
+
+        if serial != expected {
+            Err(SimpleModulusError::InvalidSerial(expected, serial))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn size_field_end(kind: u8) -> usize {
+    match kind {
+        0xC1 | 0xC3 => 2,
+        _ => 3,
+    }
+}
+
+fn read_u32_le(buf: &[u8]) -> u32 {
+    u32::from(buf[0]) | u32::from(buf[1]) << 8 | u32::from(buf[2]) << 16 | u32::from(buf[3]) << 24
+}
+
+/// Writes values MSB first into a byte buffer, the way the cipher packs its 18 bit words.
+struct BitWriter<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> BitWriter<'a> {
+    fn new(buf: &'a mut [u8]) -> BitWriter<'a> {
+        for b in buf.iter_mut() {
+            *b = 0;
+        }
+        BitWriter { buf, pos: 0 }
+    }
+
+    fn write(&mut self, val: u32, bits: usize) {
+        for i in (0..bits).rev() {
+            if (val >> i) & 1 == 1 {
+                self.buf[self.pos / 8] |= 0x80 >> (self.pos % 8);
+            }
+            self.pos += 1;
+        }
+    }
+}
+
+struct BitReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(buf: &'a [u8]) -> BitReader<'a> {
+        BitReader { buf, pos: 0 }
+    }
+
+    fn read(&mut self, bits: usize) -> u32 {
+        let mut val = 0;
+        for _ in 0..bits {
+            let bit = (self.buf[self.pos / 8] >> (7 - self.pos % 8)) & 1;
+            val = val << 1 | u32::from(bit);
+            self.pos += 1;
+        }
+        val
+    }
+}
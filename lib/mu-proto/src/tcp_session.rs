@@ -1,130 +1,202 @@
-extern crate futures;
-extern crate tokio_core;
-extern crate tokio_io;
-
 use super::MuPacket;
-use self::tokio_io::{AsyncRead, AsyncWrite};
-use self::tokio_io::io::{ReadHalf, WriteHalf};
-use self::futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+use super::codec::MuCodec;
+use super::simple_modulus::SimpleModulus;
+use super::server::SessionOptions;
+use tokio::io::{AsyncRead, AsyncWrite, ReadHalf, WriteHalf};
+use tokio_util::codec::FramedRead;
+use futures::Sink;
+use bytes::Bytes;
 
 use failure::Error;
 
-use std::io;
-use std::io::{Read, Write};
-use std::marker::PhantomData;
+use std::collections::VecDeque;
+use std::io::IoSlice;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
 
 #[derive(Debug, Fail)]
 pub enum TcpSessionError {
-    #[fail(display = "Failed to read from TCP Stream")]
-    TcpStreamRead,
     #[fail(display = "Failed to write into TCP Stream")]
     TcpStreamWrite,
     #[fail(display = "Failed to flush into TCP Stream")]
     TcpStreamFlush,
-    #[fail(display = "Failed to serialize packet")]
-    SerializationError,
     #[fail(display = "Stream was closed")]
     Closed,
+    #[fail(display = "Encrypted packet received, but no SimpleModulus keys were loaded")]
+    MissingCipher,
 }
 
-pub struct TcpSession<T> {
-    _io: PhantomData<T>,
-}
+/// Free room kept on the read buffer before each read.
+const READ_CHUNK: usize = 10_240;
 
-pub struct TcpSessionReader<T> {
-    io: T,
-    pub id: u32,
-    buf: [u8; 10_240],
-}
+/// Outbound bytes a session may hold before `poll_ready` pushes back on the caller.
+const MAX_PENDING: usize = 64 * 1024;
+
+/// Most frames handed to a single vectored write.
+const MAX_IOVECS: usize = 32;
+
+/// Stream of the plain packets received by a session.
+pub type TcpSessionReader<T> = FramedRead<T, MuCodec>;
 
+/// Sink of the packets sent by a session. Frames are encoded by the session codec, but kept
+/// queued as they are, so several of them go out on a single vectored write without copies.
 pub struct TcpSessionWriter<T> {
     io: T,
-    pub id: u32,
+    codec: MuCodec,
+    //Encoded frames not written yet. The front one may be partially sent already.
+    out: VecDeque<Bytes>,
+    out_len: usize,
 }
 
-impl<T> TcpSession<T>
+/// Splits a stream into the reader and the writer of a session, sharing the session codec.
+pub fn new_pair<T>(
+    io: T,
+    id: u32,
+    kind: u8,
+    cipher: Option<Arc<SimpleModulus>>,
+    opts: &SessionOptions,
+) -> (TcpSessionReader<ReadHalf<T>>, TcpSessionWriter<WriteHalf<T>>)
 where
     T: AsyncRead + AsyncWrite,
 {
-    pub fn new_pair(
-        io: T,
-        id: u32,
-    ) -> (TcpSessionReader<ReadHalf<T>>, TcpSessionWriter<WriteHalf<T>>) {
-        let (r, w) = io.split();
-        (
-            TcpSessionReader {
-                io: r,
-                id: id,
-                buf: [0; 10_240],
-            },
-            TcpSessionWriter { io: w, id: id },
-        )
+    let (r, w) = tokio::io::split(io);
+    let codec = MuCodec::new(id, kind, cipher, opts);
+
+    (
+        FramedRead::with_capacity(r, codec.clone(), READ_CHUNK),
+        TcpSessionWriter::new(w, codec),
+    )
+}
+
+impl<T> TcpSessionWriter<T> {
+    pub fn new(io: T, codec: MuCodec) -> TcpSessionWriter<T> {
+        TcpSessionWriter {
+            io,
+            codec,
+            out: VecDeque::new(),
+            out_len: 0,
+        }
+    }
+
+    /// Encoded bytes queued but not written yet.
+    pub fn pending_len(&self) -> usize {
+        self.out_len
     }
 }
 
-impl<T> Stream for TcpSessionReader<ReadHalf<T>>
+impl<T> TcpSessionWriter<T>
 where
-    T: AsyncRead,
+    T: AsyncWrite + Unpin,
 {
-    type Item = MuPacket;
-    type Error = Error;
-    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        match self.io.read(&mut self.buf) {
-            Err(e) => {
-                if e.kind() == io::ErrorKind::WouldBlock {
-                    Ok(Async::NotReady)
-                } else {
-                    Err(TcpSessionError::TcpStreamRead)?
+    /// Writes queued frames until the queue is empty or the socket would block. Several frames
+    /// go out on a single vectored write, and short writes keep the unsent tail queued.
+    fn poll_write_pending(&mut self, cx: &mut Context) -> Poll<Result<(), Error>> {
+        while !self.out.is_empty() {
+            let res = {
+                let mut slices = [IoSlice::new(&[]); MAX_IOVECS];
+                let cnt = self.out.len().min(MAX_IOVECS);
+
+                for (slice, frame) in slices.iter_mut().zip(self.out.iter()) {
+                    *slice = IoSlice::new(frame);
+                }
+
+                Pin::new(&mut self.io).poll_write_vectored(cx, &slices[..cnt])
+            };
+
+            match res {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(_)) | Poll::Ready(Ok(0)) => {
+                    return Poll::Ready(Err(TcpSessionError::TcpStreamWrite.into()))
                 }
+                Poll::Ready(Ok(n)) => self.consume(n),
+            }
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    /// Drops `n` written bytes from the front of the queue.
+    fn consume(&mut self, mut n: usize) {
+        self.out_len -= n;
+
+        while n > 0 {
+            let front = self.out[0].len();
+
+            if n < front {
+                let _ = self.out[0].split_to(n);
+                break;
             }
-            Ok(0) => Ok(Async::Ready(None)),
-            Ok(n) => Ok(Async::Ready(MuPacket::new(&self.buf[0..n]))),
+
+            self.out.pop_front();
+            n -= front;
         }
     }
 }
 
-impl<T> Sink for TcpSessionWriter<WriteHalf<T>>
+impl<T> Sink<MuPacket> for TcpSessionWriter<T>
 where
-    T: AsyncWrite,
+    T: AsyncWrite + Unpin,
 {
-    type SinkItem = MuPacket;
-    type SinkError = Error;
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+        let this = self.get_mut();
+
+        //A slow client must not grow the queue forever, so try to drain it before refusing.
+        if this.out_len >= MAX_PENDING {
+            if let Poll::Ready(Err(e)) = this.poll_write_pending(cx) {
+                return Poll::Ready(Err(e));
+            }
+
+            if this.out_len >= MAX_PENDING {
+                return Poll::Pending;
+            }
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: MuPacket) -> Result<(), Error> {
+        let this = self.get_mut();
 
-    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
         if item.is_empty() {
             return Err(TcpSessionError::Closed)?;
         }
 
-        let mut buf = vec![0; item.len()];
+        let buf = this.codec.encode_frame(&item)?;
+        this.out_len += buf.len();
+        this.out.push_back(buf);
+
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+        let this = self.get_mut();
 
-        if let Err(_) = item.serialize(&mut buf) {
-            return Err(TcpSessionError::SerializationError)?;
+        match this.poll_write_pending(cx) {
+            Poll::Ready(Ok(())) => (),
+            other => return other,
         }
 
-        match self.io.write(&buf) {
-            Err(e) => {
-                if e.kind() == io::ErrorKind::WouldBlock {
-                    Ok(AsyncSink::NotReady(item))
-                } else {
-                    Err(TcpSessionError::TcpStreamWrite)?
-                }
-            }
-            Ok(_) => Ok(AsyncSink::Ready),
+        match Pin::new(&mut this.io).poll_flush(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Err(_)) => Poll::Ready(Err(TcpSessionError::TcpStreamFlush.into())),
+            Poll::Ready(Ok(())) => Poll::Ready(Ok(())),
         }
     }
 
-    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
-        match self.io.flush() {
-            Err(e) => {
-                if e.kind() == io::ErrorKind::WouldBlock {
-                    Ok(Async::NotReady)
-                } else {
-                    Err(TcpSessionError::TcpStreamFlush)?
-                }
-            }
-            Ok(()) => {
-                Ok(Async::Ready(()))
-            }
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+        //The close request waits until every queued frame has been written.
+        match self.as_mut().poll_flush(cx) {
+            Poll::Ready(Ok(())) => (),
+            other => return other,
+        }
+
+        match Pin::new(&mut self.io).poll_shutdown(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Err(_)) => Poll::Ready(Err(TcpSessionError::TcpStreamWrite.into())),
+            Poll::Ready(Ok(())) => Poll::Ready(Ok(())),
         }
     }
 }
@@ -0,0 +1,126 @@
+use std::fmt;
+use std::sync::Arc;
+
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+use super::endpoint::Endpoint;
+
+#[derive(Debug, Fail)]
+pub enum TlsError {
+    #[fail(display = "Failed to read {}: {}", _0, _1)]
+    InvalidFile(String, String),
+    #[fail(display = "No certificate found in {}", _0)]
+    NoCertificates(String),
+    #[fail(display = "Invalid TLS setup: {}", _0)]
+    InvalidConfig(String),
+    #[fail(display = "Invalid TLS server name: {}", _0)]
+    InvalidServerName(String),
+}
+
+/// Mutual TLS of links between our servers. Both sides show a certificate signed by the same
+/// CA, so a listener only accepts our own servers and a link only talks to our own listeners.
+#[derive(Clone)]
+pub struct TlsConfig {
+    server: Arc<ServerConfig>,
+    client: Arc<ClientConfig>,
+    server_name: Option<ServerName<'static>>,
+}
+
+impl TlsConfig {
+    /// Loads our certificate chain and private key, and the CA that signs every peer, from
+    /// PEM files.
+    pub fn load(cert: &str, key: &str, ca: &str) -> Result<TlsConfig, TlsError> {
+        let chain = load_certs(cert)?;
+        let key = PrivateKeyDer::from_pem_file(key)
+            .map_err(|e| TlsError::InvalidFile(key.to_owned(), e.to_string()))?;
+
+        let mut roots = RootCertStore::empty();
+        for ca_cert in load_certs(ca)? {
+            roots.add(ca_cert).map_err(invalid_config)?;
+        }
+        let roots = Arc::new(roots);
+
+        let provider = Arc::new(ring::default_provider());
+
+        let verifier = WebPkiClientVerifier::builder_with_provider(roots.clone(), provider.clone())
+            .build()
+            .map_err(invalid_config)?;
+
+        let server = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(invalid_config)?
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(chain.clone(), key.clone_key())
+            .map_err(invalid_config)?;
+
+        let client = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(invalid_config)?
+            .with_root_certificates(roots)
+            .with_client_auth_cert(chain, key)
+            .map_err(invalid_config)?;
+
+        Ok(TlsConfig {
+            server: Arc::new(server),
+            client: Arc::new(client),
+            server_name: None,
+        })
+    }
+
+    /// Name the certificate of listeners must have. Unless it is set, links check the address
+    /// they connect to, or localhost on Unix sockets.
+    pub fn with_server_name(mut self, name: &str) -> Result<TlsConfig, TlsError> {
+        let name = ServerName::try_from(name.to_owned())
+            .map_err(|_| TlsError::InvalidServerName(name.to_owned()))?;
+
+        self.server_name = Some(name);
+        Ok(self)
+    }
+
+    pub(crate) fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(Arc::clone(&self.server))
+    }
+
+    pub(crate) fn connector(&self) -> TlsConnector {
+        TlsConnector::from(Arc::clone(&self.client))
+    }
+
+    pub(crate) fn server_name(&self, endpoint: &Endpoint) -> ServerName<'static> {
+        if let Some(ref name) = self.server_name {
+            return name.clone();
+        }
+
+        match *endpoint {
+            Endpoint::Tcp(addr) => ServerName::IpAddress(addr.ip().into()),
+            #[cfg(unix)]
+            Endpoint::Unix(_) => ServerName::try_from("localhost").unwrap(),
+        }
+    }
+}
+
+impl fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TlsConfig({:?})", self.server_name)
+    }
+}
+
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|iter| iter.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| TlsError::InvalidFile(path.to_owned(), e.to_string()))?;
+
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificates(path.to_owned()));
+    }
+
+    Ok(certs)
+}
+
+fn invalid_config<E: fmt::Display>(e: E) -> TlsError {
+    TlsError::InvalidConfig(e.to_string())
+}
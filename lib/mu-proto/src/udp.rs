@@ -0,0 +1,108 @@
+use std::fmt;
+use std::net::{self, SocketAddr};
+use std::sync::Arc;
+
+use bytes::BytesMut;
+use failure::Error;
+use tokio::net::UdpSocket;
+use tokio_util::codec::Decoder;
+
+use super::codec::MuCodec;
+use super::packet::MuPacket;
+use super::profile::ProtoProfile;
+use super::protocol::Protocol;
+use super::server::{NetworkError, SessionOptions};
+use super::simple_modulus::SimpleModulus;
+
+/// Biggest datagram a socket receives, the most a UDP payload may hold.
+pub(crate) const MAX_DATAGRAM_LEN: usize = 65_507;
+
+/// A UDP socket bound by `Server::start_udp`, used to send datagrams and to tell where received
+/// ones arrived. Each datagram holds whole packets and is encoded on its own, so packet serials
+/// of C3/C4 packets start over on every datagram, as datagrams may be lost or reordered.
+#[derive(Clone)]
+pub struct UdpSocketRef {
+    pub kind: u8,
+    pub profile: ProtoProfile,
+    socket: Arc<UdpSocket>,
+    //Same socket, written without going through the runtime, so sends never wait on it.
+    sender: Arc<net::UdpSocket>,
+    cipher: Option<Arc<SimpleModulus>>,
+    opts: Arc<SessionOptions>,
+}
+
+impl UdpSocketRef {
+    /// Takes a socket already set as non-blocking.
+    pub(crate) fn new(
+        socket: net::UdpSocket,
+        kind: u8,
+        cipher: Option<Arc<SimpleModulus>>,
+        opts: SessionOptions,
+    ) -> Result<UdpSocketRef, NetworkError> {
+        let sender = socket.try_clone().map_err(|_| NetworkError::IoErrror)?;
+        let socket = UdpSocket::from_std(socket).map_err(|_| NetworkError::IoErrror)?;
+
+        Ok(UdpSocketRef {
+            kind,
+            profile: opts.profile,
+            socket: Arc::new(socket),
+            sender: Arc::new(sender),
+            cipher,
+            opts: Arc::new(opts),
+        })
+    }
+
+    /// Address the socket is bound to, with the actual port when bound on port 0.
+    pub fn local_addr(&self) -> Result<SocketAddr, NetworkError> {
+        self.socket.local_addr().map_err(|_| NetworkError::IoErrror)
+    }
+
+    /// Sends the packet as a single datagram. Datagrams are best effort: one the socket can't
+    /// take right away is dropped and reported as an error.
+    pub fn send_to(&self, pkt: &MuPacket, addr: SocketAddr) -> Result<(), NetworkError> {
+        let buf = self.codec().encode_frame(pkt).map_err(|_| NetworkError::DatagramSendError)?;
+
+        match self.sender.send_to(&buf, addr) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(NetworkError::DatagramSendError),
+        }
+    }
+
+    /// Serializes the message using the profile of this socket and sends it.
+    pub fn send_msg_to<P: Protocol>(&self, msg: &P, addr: SocketAddr) -> Result<(), NetworkError> {
+        let pkt = msg.to_packet(self.profile)?;
+        self.send_to(&pkt, addr)
+    }
+
+    pub(crate) async fn recv_from(&self, buf: &mut [u8]) -> std::io::Result<(usize, SocketAddr)> {
+        self.socket.recv_from(buf).await
+    }
+
+    /// Cuts a received datagram into its packets.
+    pub(crate) fn decode(&self, datagram: &[u8]) -> Result<Vec<MuPacket>, Error> {
+        let mut codec = self.codec();
+        let mut buf = BytesMut::from(datagram);
+        let mut pkts = vec![];
+
+        while let Some(pkt) = codec.decode(&mut buf)? {
+            pkts.push(pkt);
+        }
+
+        if !buf.is_empty() {
+            return Err(NetworkError::TruncatedDatagram)?;
+        }
+
+        Ok(pkts)
+    }
+
+    //Datagrams have no session, so they are captured as session 0.
+    fn codec(&self) -> MuCodec {
+        MuCodec::new(0, self.kind, self.cipher.clone(), &self.opts)
+    }
+}
+
+impl fmt::Debug for UdpSocketRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "UdpSocketRef({}, {:?})", self.kind, self.socket.local_addr().ok())
+    }
+}
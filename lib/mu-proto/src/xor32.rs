@@ -0,0 +1,33 @@
+/// Rolling key used by retail clients to obfuscate the body of their packets.
+pub const XOR32_KEY: [u8; 32] = [
+    0xE7, 0x6D, 0x3A, 0x89, 0xBC, 0xB2, 0x9F, 0x73, 0x23, 0xA8, 0xFE, 0xB6, 0x49, 0x5D, 0x39, 0x5D,
+    0x8A, 0xCB, 0x63, 0x8D, 0xEA, 0x7D, 0x2B, 0x5F, 0xC3, 0xB1, 0xE9, 0x83, 0x29, 0x51, 0xE8, 0x56,
+];
+
+/// Index of the packet code inside a plain frame. The code itself is never obfuscated,
+/// only the bytes after it.
+fn code_idx(frame: &[u8]) -> usize {
+    match frame.first() {
+        Some(&0xC1) | Some(&0xC3) => 2,
+        //Empty frames end up with an empty range, so nothing is touched.
+        _ => 3,
+    }
+}
+
+/// Reverts the XOR32 obfuscation of a whole plain frame, header included.
+pub fn decode(frame: &mut [u8]) {
+    let start = code_idx(frame);
+
+    for i in (start + 1..frame.len()).rev() {
+        frame[i] ^= frame[i - 1] ^ XOR32_KEY[i % 32];
+    }
+}
+
+/// Applies the XOR32 obfuscation on a whole plain frame, header included.
+pub fn encode(frame: &mut [u8]) {
+    let start = code_idx(frame);
+
+    for i in start + 1..frame.len() {
+        frame[i] ^= frame[i - 1] ^ XOR32_KEY[i % 32];
+    }
+}
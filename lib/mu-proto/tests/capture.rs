@@ -0,0 +1,83 @@
+//! Packet capture files, written through `Capture` and read back by `CaptureReader`.
+
+extern crate mu_proto;
+
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use mu_proto::{Capture, CaptureDirection, CaptureError, CaptureReader, ConnectResult, MuPacket,
+               ProtoProfile, Protocol};
+
+fn capture_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("mu-proto-{}-{}.mucap", name, process::id()))
+}
+
+fn write_capture(name: &str) -> PathBuf {
+    let path = capture_path(name);
+    let capture = Capture::create(&path).unwrap();
+
+    let hello = ConnectResult { res: 1 }.to_packet(ProtoProfile::default()).unwrap();
+    let list = MuPacket::new(&[0xC2, 0x00, 0x07, 0xF4, 0x06, 0x00, 0x00]).unwrap();
+
+    capture.record(7, 1, CaptureDirection::Outbound, &hello).unwrap();
+    capture.clone().record(7, 1, CaptureDirection::Inbound, &list).unwrap();
+    capture.flush().unwrap();
+
+    path
+}
+
+#[test]
+fn records_round_trip() {
+    let path = write_capture("round-trip");
+
+    let records: Vec<_> = CaptureReader::open(&path).unwrap().map(|r| r.unwrap()).collect();
+    fs::remove_file(&path).ok();
+
+    assert_eq!(records.len(), 2);
+
+    assert_eq!(records[0].session, 7);
+    assert_eq!(records[0].kind, 1);
+    assert_eq!(records[0].direction, CaptureDirection::Outbound);
+    assert_eq!(records[0].frame, [0xC1, 0x04, 0x00, 0x01]);
+
+    assert_eq!(records[1].direction, CaptureDirection::Inbound);
+    assert_eq!(records[1].frame, [0xC2, 0x00, 0x07, 0xF4, 0x06, 0x00, 0x00]);
+    assert!(records[1].time >= records[0].time);
+}
+
+#[test]
+fn reports_truncated_records() {
+    let path = write_capture("truncated");
+    let data = fs::read(&path).unwrap();
+    fs::remove_file(&path).ok();
+
+    //Cut inside the frame of the last record, then inside its header.
+    for cut in &[1, 10] {
+        let mut reader = CaptureReader::new(&data[..data.len() - cut]).unwrap();
+
+        assert!(reader.next_record().unwrap().is_some());
+        match reader.next_record() {
+            Err(CaptureError::Truncated) => (),
+            other => panic!("expected a truncated record, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn rejects_other_files() {
+    let invalid = |data: &[u8]| match CaptureReader::new(data) {
+        Err(CaptureError::InvalidHeader) => (),
+        Err(e) => panic!("expected an invalid header, got {:?}", e),
+        Ok(_) => panic!("expected an invalid header"),
+    };
+
+    invalid(b"PCAP\x01");
+    invalid(b"MUCP\x02");
+    invalid(b"MUC");
+    invalid(b"");
+
+    //A header alone is an empty capture.
+    let mut reader = CaptureReader::new(&b"MUCP\x01"[..]).unwrap();
+    assert!(reader.next_record().unwrap().is_none());
+}
@@ -0,0 +1,34 @@
+#!/bin/sh
+#Regenerates the certificates of tests/tls.rs: a CA signing a server and a client certificate
+#for localhost, and a client signed by a CA nobody trusts. Needs the openssl command line.
+set -e
+cd "$(dirname "$0")"
+
+DAYS=36500
+
+key() {
+    openssl genpkey -algorithm EC -pkeyopt ec_paramgen_curve:P-256 -out "$1.key"
+}
+
+ca() {
+    key "$1"
+    openssl req -x509 -new -key "$1.key" -subj "/CN=$2" -days $DAYS -out "$1.pem" \
+        -addext "basicConstraints=critical,CA:TRUE" \
+        -addext "keyUsage=critical,keyCertSign,cRLSign"
+}
+
+leaf() {
+    key "$1"
+    openssl req -new -key "$1.key" -subj "/CN=$1" -out "$1.csr"
+    printf "subjectAltName=DNS:localhost,IP:127.0.0.1\nextendedKeyUsage=%s\nbasicConstraints=CA:FALSE\n" "$3" > "$1.ext"
+    openssl x509 -req -in "$1.csr" -CA "$2.pem" -CAkey "$2.key" -CAcreateserial \
+        -days $DAYS -extfile "$1.ext" -out "$1.pem"
+    rm "$1.csr" "$1.ext"
+}
+
+ca ca "mu-proto test CA"
+ca rogue-ca "mu-proto rogue CA"
+leaf server ca serverAuth
+leaf client ca clientAuth
+leaf rogue rogue-ca clientAuth
+rm -f ./*.srl
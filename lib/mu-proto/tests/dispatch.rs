@@ -0,0 +1,55 @@
+//! Routing of inbound packets to the handlers registered on a `Dispatcher`.
+
+extern crate mu_proto;
+
+use std::sync::Arc;
+
+use mu_proto::prelude::SessionRef;
+use mu_proto::{ConnectResult, DispatchError, Dispatcher, Endpoint, MuPacket, OverflowPolicy,
+               ProtoMsg, ProtoProfile, Protocol, SessionQueue};
+
+#[derive(Default)]
+struct Handler {
+    results: Vec<u8>,
+}
+
+impl Handler {
+    fn on_connect_result(&mut self, _session: SessionRef, msg: ConnectResult) {
+        self.results.push(msg.res);
+    }
+}
+
+fn session() -> SessionRef {
+    let queue = Arc::new(SessionQueue::new(10, OverflowPolicy::default()));
+    let endpoint: Endpoint = "127.0.0.1:55901".parse().unwrap();
+    SessionRef::new(1, 1, ProtoProfile::default(), queue, endpoint)
+}
+
+#[test]
+fn dispatches_registered_messages() {
+    let mut dispatcher = Dispatcher::new();
+    dispatcher.register(Handler::on_connect_result);
+
+    let mut handler = Handler::default();
+    let pkt = ConnectResult { res: 1 }.to_packet(ProtoProfile::default()).unwrap();
+
+    dispatcher.dispatch(&mut handler, session(), &pkt).unwrap();
+    assert_eq!(handler.results, [1]);
+}
+
+#[test]
+fn reports_what_it_can_not_dispatch() {
+    let dispatcher: Dispatcher<Handler> = Dispatcher::new();
+    let mut handler = Handler::default();
+
+    let pkt = ConnectResult { res: 1 }.to_packet(ProtoProfile::default()).unwrap();
+    match dispatcher.dispatch(&mut handler, session(), &pkt) {
+        Err(DispatchError::Unhandled(ProtoMsg::ConnectResult)) => (),
+        other => panic!("expected an unhandled message, got {:?}", other),
+    }
+
+    //The header of an unknown packet is kept, so logs tell which packet it was.
+    let pkt = MuPacket::new(&[0xC1, 0x05, 0xF3, 0x01, 0x02]).unwrap();
+    let err = dispatcher.dispatch(&mut handler, session(), &pkt).unwrap_err();
+    assert_eq!(err.to_string(), "Unknown packet: C1 F3 00");
+}
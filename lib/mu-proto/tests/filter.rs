@@ -0,0 +1,98 @@
+//! CIDR blocks and the allow/deny filter of listeners.
+
+extern crate futures;
+extern crate mu_proto;
+extern crate tokio;
+
+use std::net::IpAddr;
+
+use futures::StreamExt;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+
+use mu_proto::{IpFilter, IpNet, IpRules, NetworkEvent, Server, SessionOptions};
+
+fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+fn nets(list: &[&str]) -> Vec<IpNet> {
+    list.iter().map(|s| s.parse().unwrap()).collect()
+}
+
+#[test]
+fn cidr_blocks() {
+    let net: IpNet = "10.1.0.0/16".parse().unwrap();
+    assert!(net.contains(ip("10.1.255.3")));
+    assert!(!net.contains(ip("10.2.0.1")));
+    assert!(net.contains(ip("::ffff:10.1.0.9")));
+    assert!(!net.contains(ip("fd00::1")));
+
+    let host: IpNet = "192.168.0.7".parse().unwrap();
+    assert_eq!(host.to_string(), "192.168.0.7/32");
+    assert!(host.contains(ip("192.168.0.7")));
+    assert!(!host.contains(ip("192.168.0.8")));
+
+    let any: IpNet = "0.0.0.0/0".parse().unwrap();
+    assert!(any.contains(ip("8.8.8.8")));
+
+    let v6: IpNet = "fd00::/8".parse().unwrap();
+    assert!(v6.contains(ip("fd12::1")));
+    assert!(!v6.contains(ip("fe80::1")));
+
+    for bad in ["10.0.0.0/33", "fd00::/129", "10.0.0/8", "10.0.0.0/", "local"].iter() {
+        assert!(bad.parse::<IpNet>().is_err(), "{} should be rejected", bad);
+    }
+}
+
+#[test]
+fn deny_wins_over_allow() {
+    let rules = IpRules {
+        allow: nets(&["10.0.0.0/8"]),
+        deny: nets(&["10.0.0.13"]),
+    };
+
+    assert!(rules.allows(ip("10.0.0.12")));
+    assert!(!rules.allows(ip("10.0.0.13")));
+    assert!(!rules.allows(ip("172.16.0.1")));
+
+    let open = IpRules {
+        allow: vec![],
+        deny: nets(&["172.16.0.0/12"]),
+    };
+    assert!(open.allows(ip("10.0.0.13")));
+    assert!(!open.allows(ip("172.20.0.1")));
+}
+
+#[tokio::test]
+async fn listener_applies_updated_rules() {
+    let filter = IpFilter::new(IpRules {
+        allow: nets(&["10.0.0.0/8"]),
+        deny: vec![],
+    });
+
+    let opts = SessionOptions {
+        filter: Some(filter.clone()),
+        ..SessionOptions::default()
+    };
+
+    let mut server = Server::new();
+    server.start_tcp("127.0.0.1", 47121, 1, opts).unwrap();
+
+    //Rejected connections are closed without a word.
+    let mut client = TcpStream::connect("127.0.0.1:47121").await.unwrap();
+    let mut received = vec![];
+    client.read_to_end(&mut received).await.unwrap();
+    assert!(received.is_empty());
+
+    filter.update(IpRules {
+        allow: nets(&["127.0.0.0/8"]),
+        deny: vec![],
+    });
+
+    let _client = TcpStream::connect("127.0.0.1:47121").await.unwrap();
+    match server.next().await {
+        Some(NetworkEvent::ClientConnected(_)) => (),
+        other => panic!("expected a connection, got {:?}", other),
+    }
+}
@@ -0,0 +1,194 @@
+//! Wire format of every message, byte by byte.
+//!
+//! Every vector here is built by hand, none was captured from a reference server. Messages read
+//! by clients follow the documented Season 6 layout, messages only exchanged between the
+//! servers pin the layout defined on protocol.rs. Captured frames should replace them once
+//! available, recorded with `Capture` and checked with `mu-dissect`.
+
+extern crate mu_proto;
+
+use mu_proto::{ConnectResult, JoinServerStat, Message, MuPacket, Ping, Pong, ProtoMsg,
+               ProtoProfile, Protocol, ServerInfo, ServerList};
+
+/// Checks both ways: the message serializes to `wire` and `wire` decodes to the message.
+fn check<P: Protocol>(msg: P, expected: Message, profile: ProtoProfile, wire: &[u8]) {
+    let pkt = msg.to_packet(profile).unwrap();
+    assert_eq!(&pkt.frame()[..], wire, "serialize {:?} on {}", expected, profile);
+
+    let pkt = MuPacket::new(wire).unwrap();
+    assert_eq!(Message::decode(&pkt, profile).unwrap(), expected, "decode on {}", profile);
+}
+
+fn server_info() -> ServerInfo {
+    let mut ip = [0u8; 16];
+    ip[..9].copy_from_slice(b"127.0.0.1");
+
+    ServerInfo {
+        svr_code: 0x0001,
+        ip,
+        port: 55901,
+        perc: 5,
+        usr_cnt: 10,
+        acc_cnt: 12,
+        mx_usr_cnt: 100,
+    }
+}
+
+#[test]
+fn server_info_wire() {
+    #[rustfmt::skip]
+    let wire = [
+        0xC1, 0x1E, 0x01,
+        0x00, 0x01, //svr_code
+        0x31, 0x32, 0x37, 0x2E, 0x30, 0x2E, 0x30, 0x2E, 0x31, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, //ip
+        0xDA, 0x5D, //port
+        0x05, //perc
+        0x00, 0x0A, //usr_cnt
+        0x00, 0x0C, //acc_cnt
+        0x00, 0x64, //mx_usr_cnt
+    ];
+
+    for profile in ProtoProfile::all() {
+        check(server_info(), Message::ServerInfo(server_info()), *profile, &wire);
+    }
+}
+
+#[test]
+fn join_server_stat_wire() {
+    let wire = [0xC1, 0x07, 0x02, 0x00, 0x00, 0x01, 0x2C];
+
+    for profile in ProtoProfile::all() {
+        let msg = JoinServerStat { queue_cnt: 300 };
+        check(msg.clone(), Message::JoinServerStat(msg), *profile, &wire);
+    }
+}
+
+#[test]
+fn connect_result_wire() {
+    //Hello sent to every client once connected.
+    let wire = [0xC1, 0x04, 0x00, 0x01];
+
+    for profile in ProtoProfile::all() {
+        let msg = ConnectResult { res: 1 };
+        check(msg.clone(), Message::ConnectResult(msg), *profile, &wire);
+    }
+}
+
+#[test]
+fn heartbeat_wire() {
+    let ping = [0xC1, 0x07, 0xFE, 0x00, 0x00, 0x01, 0x2C];
+    let pong = [0xC1, 0x07, 0xFF, 0x00, 0x00, 0x01, 0x2C];
+
+    for profile in ProtoProfile::all() {
+        let msg = Ping { seq: 300 };
+        check(msg.clone(), Message::Ping(msg), *profile, &ping);
+
+        let msg = Pong { seq: 300 };
+        check(msg.clone(), Message::Pong(msg), *profile, &pong);
+    }
+}
+
+//Server codes are the group times 20 plus the server index, so 21 is the second server of the
+//second group. Its code tells the byte order apart, unlike the small indexes of a single group.
+fn server_list() -> ServerList {
+    let mut list = ServerList::new();
+    list.add(0, 80);
+    list.add(21, 20);
+    list
+}
+
+#[test]
+fn server_list_wire() {
+    //Season 6 list: big endian count, then little endian server codes.
+    #[rustfmt::skip]
+    let wire = [
+        0xC2, 0x00, 0x0F, 0xF4, 0x06,
+        0x00, 0x02, //count
+        0x00, 0x00, 0x50, 0xFF, //idx, load, unk
+        0x15, 0x00, 0x14, 0xFF,
+    ];
+
+    for profile in &[ProtoProfile::V104d, ProtoProfile::Season6] {
+        check(server_list(), Message::ServerList(server_list()), *profile, &wire);
+    }
+}
+
+#[test]
+fn server_list_097d_wire() {
+    #[rustfmt::skip]
+    let wire = [
+        0xC2, 0x00, 0x0D, 0xF4, 0x02,
+        0x00, 0x02, //count
+        0x00, 0x00, 0x50, //idx, load
+        0x15, 0x00, 0x14,
+    ];
+
+    let mut expected = server_list();
+    for entry in expected.servers.iter_mut() {
+        entry.unk = 0;
+    }
+
+    check(server_list(), Message::ServerList(expected), ProtoProfile::V097d, &wire);
+}
+
+#[test]
+fn server_list_reads_little_endian_codes() {
+    //List with the servers 0 and 1 of the first group, both empty.
+    #[rustfmt::skip]
+    let frame = [
+        0xC2, 0x00, 0x0F, 0xF4, 0x06,
+        0x00, 0x02,
+        0x00, 0x00, 0x00, 0xFF,
+        0x01, 0x00, 0x00, 0xFF,
+    ];
+    let pkt = MuPacket::new(&frame).unwrap();
+
+    match Message::decode(&pkt, ProtoProfile::Season6).unwrap() {
+        Message::ServerList(list) => {
+            let idx: Vec<u16> = list.servers.iter().map(|s| s.idx).collect();
+            assert_eq!(idx, [0, 1]);
+        }
+        other => panic!("expected a server list, got {:?}", other),
+    }
+}
+
+#[test]
+fn server_list_code_depends_on_the_version() {
+    assert_eq!(ProtoProfile::V097d.header(ProtoMsg::ServerList), (0xC2, 0xF4, 0x02));
+    assert_eq!(ProtoProfile::V104d.header(ProtoMsg::ServerList), (0xC2, 0xF4, 0x06));
+    assert_eq!(ProtoProfile::Season6.header(ProtoMsg::ServerList), (0xC2, 0xF4, 0x06));
+
+    //Each version only knows its own code.
+    assert_eq!(ProtoMsg::lookup(0xC2, 0xF4, 0x02, ProtoProfile::Season6), None);
+    assert_eq!(ProtoMsg::lookup(0xC2, 0xF4, 0x06, ProtoProfile::V097d), None);
+    assert_eq!(
+        ProtoMsg::lookup(0xC2, 0xF4, 0x02, ProtoProfile::V097d),
+        Some(ProtoMsg::ServerList)
+    );
+}
+
+#[test]
+fn encrypted_kinds_share_the_plain_headers() {
+    let pkt = MuPacket::new(&[0xC3, 0x04, 0x00, 0x01]).unwrap();
+
+    assert!(pkt.is_encrypted());
+    assert_eq!(
+        Message::decode(&pkt, ProtoProfile::Season6).unwrap(),
+        Message::ConnectResult(ConnectResult { res: 1 })
+    );
+}
+
+#[test]
+fn malformed_frames_are_rejected() {
+    assert!(MuPacket::new(&[]).is_err());
+    assert!(MuPacket::new(&[0xC1]).is_err());
+    assert!(MuPacket::new(&[0xC1, 0x05, 0x00, 0x01]).is_err());
+    assert!(MuPacket::new(&[0xC5, 0x04, 0x00, 0x01]).is_err());
+    assert!(MuPacket::new(&[0xC2, 0x00, 0x04, 0xF4]).is_err());
+
+    //Server list saying it holds more entries than the body has.
+    let pkt = MuPacket::new(&[0xC2, 0x00, 0x0B, 0xF4, 0x06, 0x00, 0x05, 0x00, 0x00, 0x50, 0xFF])
+        .unwrap();
+    assert!(Message::decode(&pkt, ProtoProfile::Season6).is_err());
+}
@@ -0,0 +1,125 @@
+//! Connection caps, packet rates and bans of a listener.
+
+extern crate futures;
+extern crate mu_proto;
+extern crate tokio;
+
+use std::time::Duration;
+
+use futures::StreamExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use mu_proto::{ConnectionLimits, DisconnectReason, NetworkEvent, Server, SessionOptions};
+
+fn start(port: u16, limits: ConnectionLimits) -> Server {
+    let opts = SessionOptions {
+        limits,
+        ..SessionOptions::default()
+    };
+
+    let mut server = Server::new();
+    server.start_tcp("127.0.0.1", port, 1, opts).unwrap();
+    server
+}
+
+async fn connect(server: &mut Server, port: u16) -> TcpStream {
+    let client = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+
+    match server.next().await {
+        Some(NetworkEvent::ClientConnected(_)) => client,
+        other => panic!("expected a connection, got {:?}", other),
+    }
+}
+
+/// Refused connections are accepted by the OS, then closed without a word.
+async fn assert_refused(port: u16) {
+    let mut client = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    let mut received = vec![];
+    client.read_to_end(&mut received).await.unwrap();
+    assert!(received.is_empty());
+}
+
+async fn assert_disconnected(server: &mut Server, expected: DisconnectReason) {
+    match server.next().await {
+        Some(NetworkEvent::ClientDisconnected((_, _, reason))) => assert_eq!(reason, expected),
+        other => panic!("expected a disconnection, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn per_ip_cap_frees_slots_on_disconnect() {
+    let limits = ConnectionLimits {
+        max_per_ip: Some(2),
+        ..ConnectionLimits::default()
+    };
+    let mut server = start(47111, limits);
+
+    let first = connect(&mut server, 47111).await;
+    let _second = connect(&mut server, 47111).await;
+    assert_refused(47111).await;
+
+    drop(first);
+    assert_disconnected(&mut server, DisconnectReason::Closed).await;
+    connect(&mut server, 47111).await;
+}
+
+#[tokio::test]
+async fn global_cap() {
+    let limits = ConnectionLimits {
+        max_connections: Some(1),
+        ..ConnectionLimits::default()
+    };
+    let mut server = start(47112, limits);
+
+    let _first = connect(&mut server, 47112).await;
+    assert_refused(47112).await;
+}
+
+#[tokio::test]
+async fn packet_flood_bans_the_address() {
+    let limits = ConnectionLimits {
+        max_packets_per_sec: Some(3),
+        ban_time: Some(Duration::from_millis(300)),
+        ..ConnectionLimits::default()
+    };
+    let mut server = start(47113, limits);
+
+    let mut client = connect(&mut server, 47113).await;
+    for _ in 0..4 {
+        client.write_all(&[0xC1, 0x04, 0x00, 0x01]).await.unwrap();
+    }
+
+    for _ in 0..3 {
+        match server.next().await {
+            Some(NetworkEvent::ClientPacket(_)) => (),
+            other => panic!("expected a packet, got {:?}", other),
+        }
+    }
+    assert_disconnected(&mut server, DisconnectReason::Flood).await;
+    assert_refused(47113).await;
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    connect(&mut server, 47113).await;
+}
+
+#[tokio::test]
+async fn byte_flood_disconnects_without_ban() {
+    let limits = ConnectionLimits {
+        max_bytes_per_sec: Some(10),
+        ..ConnectionLimits::default()
+    };
+    let mut server = start(47114, limits);
+
+    let mut client = connect(&mut server, 47114).await;
+    client.write_all(&[0xC1, 0x04, 0x00, 0x01, 0xC1, 0x07, 0x02, 0x00, 0x00, 0x01, 0x2C])
+        .await
+        .unwrap();
+
+    match server.next().await {
+        Some(NetworkEvent::ClientPacket(_)) => (),
+        other => panic!("expected a packet, got {:?}", other),
+    }
+    assert_disconnected(&mut server, DisconnectReason::Flood).await;
+    connect(&mut server, 47114).await;
+}
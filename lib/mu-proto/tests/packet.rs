@@ -0,0 +1,55 @@
+//! Frames that don't fit their header are refused with an error, never truncated.
+
+extern crate mu_proto;
+
+use mu_proto::{MuPacket, MuPacketError, ProtoMsg, ProtoProfile, Protocol};
+
+//A body of any size, sent with the header of the message it stands for.
+struct Filler<const C2: bool>(usize);
+
+impl<const C2: bool> Protocol for Filler<C2> {
+    fn msg() -> ProtoMsg {
+        if C2 {
+            ProtoMsg::ServerList
+        } else {
+            ProtoMsg::ConnectResult
+        }
+    }
+
+    fn parse(buf: &[u8], _profile: ProtoProfile) -> Result<Self, MuPacketError> {
+        Ok(Filler(buf.len()))
+    }
+
+    fn serialize(&self, buf: &mut [u8], _profile: ProtoProfile) {
+        buf.fill(0xAA);
+    }
+
+    fn size(&self, _profile: ProtoProfile) -> usize {
+        self.0
+    }
+}
+
+#[test]
+fn c1_frame_up_to_255_bytes() {
+    let pkt = Filler::<false>(252).to_packet(ProtoProfile::default()).unwrap();
+    assert_eq!(pkt.len(), 255);
+    assert_eq!(pkt.frame()[1], 255);
+}
+
+#[test]
+fn oversized_c1_frame_is_refused() {
+    let res = Filler::<false>(253).to_packet(ProtoProfile::default());
+    assert_eq!(res.unwrap_err(), MuPacketError::TooLarge(0xC1, 256));
+}
+
+#[test]
+fn oversized_c2_frame_is_refused() {
+    let res = Filler::<true>(70_000).to_packet(ProtoProfile::default());
+    assert_eq!(res.unwrap_err(), MuPacketError::TooLarge(0xC2, 70_005));
+}
+
+#[test]
+fn unknown_kind_has_no_header() {
+    assert_eq!(MuPacket::header_len(&0xC5, &0x00), Err(MuPacketError::UnknownKind(0xC5)));
+    assert_eq!(MuPacket::header_len(&0xC1, &0x00), Ok(3));
+}
@@ -0,0 +1,167 @@
+//! Overflow policies and metrics of the session outbound queue.
+
+extern crate mu_proto;
+extern crate tokio;
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use mu_proto::{ConnectResult, MuPacket, NetworkError, OverflowPolicy, Protocol, ProtoProfile,
+               QueueStats, SessionQueue};
+
+fn pkt(res: u8) -> MuPacket {
+    ConnectResult { res }.to_packet(ProtoProfile::default()).unwrap()
+}
+
+fn res(pkt: &MuPacket) -> u8 {
+    pkt.data()[0]
+}
+
+async fn drain(queue: &SessionQueue) -> Vec<u8> {
+    queue.close();
+
+    let mut out = vec![];
+    while let Some(pkt) = queue.pop().await {
+        out.push(res(&pkt));
+    }
+    out
+}
+
+#[tokio::test]
+async fn disconnect_closes_and_forgets() {
+    let queue = SessionQueue::new(2, OverflowPolicy::Disconnect);
+    queue.push(pkt(1)).unwrap();
+    queue.push(pkt(2)).unwrap();
+
+    match queue.push(pkt(3)) {
+        Err(NetworkError::SessionOverflow) => (),
+        other => panic!("expected an overflow, got {:?}", other),
+    }
+
+    match queue.push(pkt(4)) {
+        Err(NetworkError::SessionDisconnected) => (),
+        other => panic!("expected a disconnection, got {:?}", other),
+    }
+
+    assert!(queue.pop().await.is_none());
+    assert_eq!(queue.stats().dropped, 3);
+}
+
+#[tokio::test]
+async fn drop_oldest_keeps_latest() {
+    let queue = SessionQueue::new(2, OverflowPolicy::DropOldest);
+
+    for i in 1..=4 {
+        queue.push(pkt(i)).unwrap();
+    }
+
+    assert_eq!(drain(&queue).await, [3, 4]);
+    assert_eq!(queue.stats().dropped, 2);
+}
+
+#[tokio::test]
+async fn drop_newest_keeps_earliest() {
+    let queue = SessionQueue::new(2, OverflowPolicy::DropNewest);
+
+    for i in 1..=4 {
+        queue.push(pkt(i)).unwrap();
+    }
+
+    assert_eq!(drain(&queue).await, [1, 2]);
+    assert_eq!(queue.stats().dropped, 2);
+}
+
+#[tokio::test]
+async fn block_times_out() {
+    let queue = SessionQueue::new(1, OverflowPolicy::Block(Duration::from_millis(50)));
+    queue.push(pkt(1)).unwrap();
+
+    let start = Instant::now();
+    match queue.push_wait(pkt(2)).await {
+        Err(NetworkError::SessionSendError) => (),
+        other => panic!("expected a timeout, got {:?}", other),
+    }
+    assert!(start.elapsed() >= Duration::from_millis(50));
+}
+
+#[tokio::test]
+async fn block_waits_for_room() {
+    let queue = Arc::new(SessionQueue::new(1, OverflowPolicy::Block(Duration::from_secs(5))));
+    queue.push(pkt(1)).unwrap();
+
+    let writer = {
+        let queue = Arc::clone(&queue);
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+
+            let mut out = vec![];
+            while let Some(pkt) = queue.pop().await {
+                out.push(res(&pkt));
+            }
+            out
+        })
+    };
+
+    queue.push_wait(pkt(2)).await.unwrap();
+    queue.close();
+
+    assert_eq!(writer.await.unwrap(), [1, 2]);
+    assert_eq!(queue.stats().dropped, 0);
+}
+
+#[tokio::test]
+async fn block_never_parks_sync_senders() {
+    let queue = SessionQueue::new(1, OverflowPolicy::Block(Duration::from_secs(5)));
+    queue.push(pkt(1)).unwrap();
+
+    let start = Instant::now();
+    match queue.push(pkt(2)) {
+        Err(NetworkError::SessionSendError) => (),
+        other => panic!("expected a full queue, got {:?}", other),
+    }
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    //Closing wakes waiting senders instead of letting them time out.
+    let queue = Arc::new(queue);
+    let closer = {
+        let queue = Arc::clone(&queue);
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            queue.shutdown();
+        })
+    };
+
+    match queue.push_wait(pkt(3)).await {
+        Err(NetworkError::SessionDisconnected) => (),
+        other => panic!("expected a closed queue, got {:?}", other),
+    }
+    closer.await.unwrap();
+}
+
+#[tokio::test]
+async fn stats_track_depth_and_high_water() {
+    let queue = SessionQueue::new(10, OverflowPolicy::Disconnect);
+
+    for i in 0..3 {
+        queue.push(pkt(i)).unwrap();
+    }
+    queue.pop().await.unwrap();
+
+    let expected = QueueStats {
+        depth: 2,
+        high_water: 3,
+        dropped: 0,
+    };
+    assert_eq!(queue.stats(), expected);
+}
+
+#[test]
+fn policy_names() {
+    for name in ["disconnect", "drop_oldest", "drop_newest", "block:250"].iter() {
+        let policy: OverflowPolicy = name.parse().unwrap();
+        assert_eq!(policy.to_string(), *name);
+    }
+
+    assert!("block:soon".parse::<OverflowPolicy>().is_err());
+    assert!("wait".parse::<OverflowPolicy>().is_err());
+}
@@ -0,0 +1,121 @@
+//! Backoff, failover and link events of connections started by `connect_to`.
+
+extern crate futures;
+extern crate mu_proto;
+extern crate tokio;
+
+use std::time::Duration;
+
+use futures::StreamExt;
+
+use mu_proto::{Endpoint, NetworkEvent, ReconnectPolicy, Server, SessionOptions};
+
+//Nothing listens on it, so connections are refused right away.
+const CLOSED: &str = "127.0.0.1:47130";
+
+fn fast_policy(max_attempts: Option<u32>) -> SessionOptions {
+    SessionOptions {
+        reconnect: ReconnectPolicy {
+            initial_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(40),
+            jitter: 0.0,
+            max_attempts,
+        },
+        ..SessionOptions::default()
+    }
+}
+
+fn addr(s: &str) -> Endpoint {
+    s.parse().unwrap()
+}
+
+#[test]
+fn backoff_doubles_up_to_the_cap() {
+    let policy = ReconnectPolicy {
+        initial_delay: Duration::from_millis(100),
+        max_delay: Duration::from_millis(1000),
+        jitter: 0.0,
+        max_attempts: Some(3),
+    };
+
+    let delays: Vec<u128> = (1..=6).map(|a| policy.delay(a).as_millis()).collect();
+    assert_eq!(delays, [100, 200, 400, 800, 1000, 1000]);
+
+    assert!(!policy.gives_up(2));
+    assert!(policy.gives_up(3));
+    assert!(!ReconnectPolicy::default().gives_up(u32::MAX));
+}
+
+#[test]
+fn jitter_stays_within_bounds() {
+    let policy = ReconnectPolicy {
+        initial_delay: Duration::from_millis(1000),
+        jitter: 0.25,
+        ..ReconnectPolicy::default()
+    };
+
+    for _ in 0..1000 {
+        let delay = policy.delay(1);
+        assert!(delay >= Duration::from_millis(750) && delay <= Duration::from_millis(1250));
+    }
+}
+
+#[tokio::test]
+async fn fails_over_and_reports_the_link() {
+    let mut standby = Server::new();
+    standby.start_tcp("127.0.0.1", 47131, 1, SessionOptions::default()).unwrap();
+
+    let mut client = Server::new();
+    let endpoints = vec![addr(CLOSED), addr("127.0.0.1:47131")];
+    client.connect_to_any(endpoints, 2, fast_policy(None)).unwrap();
+
+    match client.next().await {
+        Some(NetworkEvent::ClientConnected(session)) => assert_eq!(session.kind, 2),
+        other => panic!("expected a connection, got {:?}", other),
+    }
+
+    let mut remote = match standby.next().await {
+        Some(NetworkEvent::ClientConnected(session)) => session,
+        other => panic!("expected a connection, got {:?}", other),
+    };
+    remote.close().unwrap();
+
+    match client.next().await {
+        Some(NetworkEvent::ClientDisconnected((_, 2, _))) => (),
+        other => panic!("expected a disconnection, got {:?}", other),
+    }
+
+    match client.next().await {
+        Some(NetworkEvent::LinkLost((2, lost))) => assert_eq!(lost, addr("127.0.0.1:47131")),
+        other => panic!("expected a lost link, got {:?}", other),
+    }
+
+    match client.next().await {
+        Some(NetworkEvent::Reconnecting((2, 1))) => (),
+        other => panic!("expected a reconnection, got {:?}", other),
+    }
+
+    match client.next().await {
+        Some(NetworkEvent::ClientConnected(_)) => (),
+        other => panic!("expected a connection, got {:?}", other),
+    }
+
+    match client.next().await {
+        Some(NetworkEvent::LinkRestored(session)) => assert_eq!(session.kind, 2),
+        other => panic!("expected a restored link, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn gives_up_after_max_attempts() {
+    let mut client = Server::new();
+    client.connect_to_any(vec![addr(CLOSED)], 2, fast_policy(Some(3))).unwrap();
+
+    //The link was never up, so there is nothing to report before giving up.
+    match client.next().await {
+        Some(NetworkEvent::LinkFailed((2, 3))) => (),
+        other => panic!("expected a failed link, got {:?}", other),
+    }
+
+    assert!(client.connect_to_any(vec![], 2, SessionOptions::default()).is_err());
+}
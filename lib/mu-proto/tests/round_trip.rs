@@ -0,0 +1,102 @@
+//! Every message goes through the same path a listener uses: serialize into a `MuPacket`,
+//! write the frame bytes, read them back with `MuPacket::new` and decode the message.
+
+extern crate mu_proto;
+extern crate proptest;
+
+use mu_proto::{ConnectResult, JoinServerStat, Message, MuPacket, Ping, Pong, ProtoProfile,
+               Protocol, ServerInfo, ServerList};
+use proptest::prelude::*;
+
+fn round_trip<P: Protocol>(msg: &P, profile: ProtoProfile) -> Message {
+    let pkt = msg.to_packet(profile).unwrap();
+
+    let mut buf = vec![0; pkt.len()];
+    let len = pkt.serialize(&mut buf).unwrap();
+    assert_eq!(len, buf.len());
+
+    let pkt = MuPacket::new(&buf).unwrap();
+    assert_eq!(pkt.len(), buf.len());
+
+    Message::decode(&pkt, profile).unwrap()
+}
+
+fn profile() -> impl Strategy<Value = ProtoProfile> {
+    prop::sample::select(ProtoProfile::all().to_vec())
+}
+
+proptest! {
+    #[test]
+    fn server_info(
+        svr_code in any::<u16>(),
+        ip in any::<[u8; 16]>(),
+        port in any::<u16>(),
+        perc in any::<u8>(),
+        usr_cnt in any::<u16>(),
+        acc_cnt in any::<u16>(),
+        mx_usr_cnt in any::<u16>(),
+        profile in profile(),
+    ) {
+        let msg = ServerInfo {
+            svr_code,
+            ip,
+            port,
+            perc,
+            usr_cnt,
+            acc_cnt,
+            mx_usr_cnt,
+        };
+
+        prop_assert_eq!(round_trip(&msg, profile), Message::ServerInfo(msg));
+    }
+
+    #[test]
+    fn join_server_stat(queue_cnt in any::<u32>(), profile in profile()) {
+        let msg = JoinServerStat { queue_cnt };
+        prop_assert_eq!(round_trip(&msg, profile), Message::JoinServerStat(msg));
+    }
+
+    #[test]
+    fn connect_result(res in any::<u8>(), profile in profile()) {
+        let msg = ConnectResult { res };
+        prop_assert_eq!(round_trip(&msg, profile), Message::ConnectResult(msg));
+    }
+
+    #[test]
+    fn heartbeat(seq in any::<u32>(), profile in profile()) {
+        let msg = Ping { seq };
+        prop_assert_eq!(round_trip(&msg, profile), Message::Ping(msg));
+
+        let msg = Pong { seq };
+        prop_assert_eq!(round_trip(&msg, profile), Message::Pong(msg));
+    }
+
+    #[test]
+    fn server_list(
+        servers in prop::collection::vec((any::<u16>(), any::<u8>()), 0..200),
+        profile in profile(),
+    ) {
+        let mut msg = ServerList::new();
+        for &(idx, load) in &servers {
+            msg.add(idx, load);
+        }
+
+        //0.97d has no room for the unknown byte, so it comes back with its default value.
+        let mut expected = msg.clone();
+        if profile == ProtoProfile::V097d {
+            for entry in expected.servers.iter_mut() {
+                entry.unk = 0;
+            }
+        }
+
+        prop_assert_eq!(round_trip(&msg, profile), Message::ServerList(expected));
+    }
+
+    #[test]
+    fn truncated_bodies_are_rejected(res in any::<u16>(), cut in 1usize..4, profile in profile()) {
+        let msg = JoinServerStat { queue_cnt: u32::from(res) };
+        let pkt = msg.to_packet(profile).unwrap();
+
+        prop_assert!(JoinServerStat::parse(&pkt.data()[..4 - cut], profile).is_err());
+    }
+}
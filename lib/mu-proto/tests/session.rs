@@ -0,0 +1,239 @@
+//! Sessions end to end: a client socket talking to a `Server` through the tokio runtime.
+
+extern crate bytes;
+extern crate futures;
+extern crate mu_proto;
+extern crate tokio;
+extern crate tokio_util;
+
+use bytes::BytesMut;
+use futures::StreamExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_util::codec::Decoder;
+
+use std::time::{Duration, Instant, SystemTime};
+
+use mu_proto::{ConnectResult, DisconnectReason, Extensions, JoinServerStat, Message, MuCodec,
+               NetworkEvent, Server, SessionOptions, SessionStats};
+
+#[derive(Clone, Debug, PartialEq)]
+struct AccountId(u32);
+
+#[derive(Debug, PartialEq)]
+enum AuthStage {
+    Connected,
+    LoggedIn,
+}
+
+#[tokio::test]
+async fn server_session_round_trip() {
+    let mut server = Server::new();
+    server.start_tcp("127.0.0.1", 47101, 1, SessionOptions::default()).unwrap();
+
+    let mut client = TcpStream::connect("127.0.0.1:47101").await.unwrap();
+
+    let mut session = match server.next().await {
+        Some(NetworkEvent::ClientConnected(session)) => session,
+        other => panic!("expected a connection, got {:?}", other),
+    };
+    assert_eq!(session.kind, 1);
+
+    //Two frames on a single write, the second one split in half.
+    client.write_all(&[0xC1, 0x04, 0x00, 0x01, 0xC1, 0x07]).await.unwrap();
+    client.write_all(&[0x02, 0x00, 0x00, 0x01, 0x2C]).await.unwrap();
+
+    let expected = [
+        Message::ConnectResult(ConnectResult { res: 1 }),
+        Message::JoinServerStat(JoinServerStat { queue_cnt: 300 }),
+    ];
+
+    for msg in expected.iter() {
+        match server.next().await {
+            Some(NetworkEvent::ClientPacket((from, pkt))) => {
+                assert_eq!(from.id, session.id);
+                assert_eq!(Message::decode(&pkt, from.profile).unwrap(), *msg);
+            }
+            other => panic!("expected a packet, got {:?}", other),
+        }
+    }
+
+    session.send_msg(&JoinServerStat { queue_cnt: 300 }).unwrap();
+    session.close().unwrap();
+
+    //Closing still writes everything sent before it, then shuts the socket down.
+    let mut received = vec![];
+    client.read_to_end(&mut received).await.unwrap();
+    assert_eq!(received, [0xC1, 0x07, 0x02, 0x00, 0x00, 0x01, 0x2C]);
+    drop(client);
+
+    match server.next().await {
+        Some(NetworkEvent::ClientDisconnected((id, kind, reason))) => {
+            assert_eq!(id, session.id);
+            assert_eq!(kind, 1);
+            assert_eq!(reason, DisconnectReason::Closed);
+        }
+        other => panic!("expected a disconnection, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn heartbeat_answers_pings_and_drops_idle_peers() {
+    let opts = SessionOptions {
+        idle_timeout: Some(Duration::from_millis(300)),
+        heartbeat: Some(Duration::from_millis(100)),
+        ..SessionOptions::default()
+    };
+
+    let mut server = Server::new();
+    server.start_tcp("127.0.0.1", 47102, 2, opts).unwrap();
+
+    let mut client = TcpStream::connect("127.0.0.1:47102").await.unwrap();
+    let start = Instant::now();
+
+    let session = match server.next().await {
+        Some(NetworkEvent::ClientConnected(session)) => session,
+        other => panic!("expected a connection, got {:?}", other),
+    };
+
+    //Pings are answered by the session itself, only the other packet reaches the handler.
+    client.write_all(&[0xC1, 0x07, 0xFE, 0x00, 0x00, 0x00, 0x2A]).await.unwrap();
+    client.write_all(&[0xC1, 0x04, 0x00, 0x01]).await.unwrap();
+
+    match server.next().await {
+        Some(NetworkEvent::ClientPacket((_, pkt))) => {
+            let msg = Message::decode(&pkt, session.profile).unwrap();
+            assert_eq!(msg, Message::ConnectResult(ConnectResult { res: 1 }));
+        }
+        other => panic!("expected a packet, got {:?}", other),
+    }
+
+    //The pong comes first, then the server keeps pinging until it gives up on the client.
+    let mut received = vec![];
+    client.read_to_end(&mut received).await.unwrap();
+    assert_eq!(&received[..7], &[0xC1, 0x07, 0xFF, 0x00, 0x00, 0x00, 0x2A]);
+    assert_eq!(&received[7..14], &[0xC1, 0x07, 0xFE, 0x00, 0x00, 0x00, 0x00]);
+
+    match server.next().await {
+        Some(NetworkEvent::ClientDisconnected((id, _, reason))) => {
+            assert_eq!(id, session.id);
+            assert_eq!(reason, DisconnectReason::Idle);
+        }
+        other => panic!("expected a disconnection, got {:?}", other),
+    }
+    assert!(start.elapsed() >= Duration::from_millis(300));
+}
+
+#[tokio::test]
+async fn shutdown_drains_sessions() {
+    let mut server = Server::new();
+    server.start_tcp("127.0.0.1", 47103, 1, SessionOptions::default()).unwrap();
+
+    let mut client = TcpStream::connect("127.0.0.1:47103").await.unwrap();
+
+    let mut session = match server.next().await {
+        Some(NetworkEvent::ClientConnected(session)) => session,
+        other => panic!("expected a connection, got {:?}", other),
+    };
+
+    server.shutdown_handle().shutdown();
+
+    match server.next().await {
+        Some(NetworkEvent::ShuttingDown) => (),
+        other => panic!("expected a shutdown, got {:?}", other),
+    }
+
+    //Sessions still take a last packet, which is written before they close.
+    session.send_msg(&JoinServerStat { queue_cnt: 300 }).unwrap();
+    assert!(server.next().await.is_none());
+
+    let mut received = vec![];
+    client.read_to_end(&mut received).await.unwrap();
+    assert_eq!(received, [0xC1, 0x07, 0x02, 0x00, 0x00, 0x01, 0x2C]);
+
+    assert!(TcpStream::connect("127.0.0.1:47103").await.is_err());
+}
+
+#[tokio::test]
+async fn sessions_carry_metadata_and_extensions() {
+    let mut server = Server::new();
+    server.start_tcp("127.0.0.1", 47104, 1, SessionOptions::default()).unwrap();
+
+    let before = SystemTime::now();
+    let mut client = TcpStream::connect("127.0.0.1:47104").await.unwrap();
+
+    let mut session = match server.next().await {
+        Some(NetworkEvent::ClientConnected(session)) => session,
+        other => panic!("expected a connection, got {:?}", other),
+    };
+
+    assert_eq!(session.peer_addr(), Some(client.local_addr().unwrap()));
+    assert_eq!(session.endpoint().to_string(), client.local_addr().unwrap().to_string());
+    assert!(session.connected_at() >= before);
+    assert_eq!(session.stats(), SessionStats::default());
+
+    session.extensions().insert(AuthStage::Connected);
+
+    client.write_all(&[0xC1, 0x04, 0x00, 0x01]).await.unwrap();
+
+    //The ref of the packet event is another clone, which shares the same state.
+    let from = match server.next().await {
+        Some(NetworkEvent::ClientPacket((from, _))) => from,
+        other => panic!("expected a packet, got {:?}", other),
+    };
+
+    assert_eq!(from.extensions().remove::<AuthStage>(), Some(AuthStage::Connected));
+    from.extensions().insert(AuthStage::LoggedIn);
+    from.extensions().insert(AccountId(7));
+    assert_eq!(session.extensions().get::<AccountId>(), Some(AccountId(7)));
+    assert!(session.extensions().contains::<AuthStage>());
+
+    let stats = session.stats();
+    assert_eq!((stats.packets_in, stats.bytes_in), (1, 4));
+
+    session.send_msg(&JoinServerStat { queue_cnt: 300 }).unwrap();
+    session.close().unwrap();
+
+    let mut received = vec![];
+    client.read_to_end(&mut received).await.unwrap();
+
+    let stats = from.stats();
+    assert_eq!((stats.packets_out, stats.bytes_out), (1, 7));
+}
+
+#[test]
+fn extensions_hold_one_value_per_type() {
+    let ext = Extensions::new();
+
+    assert_eq!(ext.insert(AccountId(1)), None);
+    assert_eq!(ext.insert(AccountId(2)), Some(AccountId(1)));
+    assert!(!ext.contains::<AuthStage>());
+
+    ext.with(|id: &mut AccountId| id.0 += 1);
+    assert_eq!(ext.get::<AccountId>(), Some(AccountId(3)));
+
+    assert_eq!(ext.remove::<AccountId>(), Some(AccountId(3)));
+    assert!(!ext.contains::<AccountId>());
+}
+
+#[test]
+fn codec_waits_for_whole_frames() {
+    let mut codec = MuCodec::new(1, 1, None, &SessionOptions::default());
+    let mut buf = BytesMut::from(&[0xC1, 0x04, 0x00][..]);
+
+    assert!(codec.decode(&mut buf).unwrap().is_none());
+    assert_eq!(buf.len(), 3);
+
+    buf.extend_from_slice(&[0x01, 0xC3]);
+    let pkt = codec.decode(&mut buf).unwrap().unwrap();
+    assert_eq!(&pkt.frame()[..], &[0xC1, 0x04, 0x00, 0x01]);
+    assert_eq!(&buf[..], &[0xC3]);
+}
+
+#[test]
+fn codec_rejects_encrypted_frames_without_keys() {
+    let mut codec = MuCodec::new(1, 1, None, &SessionOptions::default());
+    let mut buf = BytesMut::from(&[0xC3, 0x0D, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0][..]);
+
+    assert!(codec.decode(&mut buf).is_err());
+}
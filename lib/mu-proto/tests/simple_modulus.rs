@@ -0,0 +1,158 @@
+//! SimpleModulus key files, C3/C4 frame encryption and packet serials.
+//!
+//! The known-answer frames were encrypted by a port of the original encoder, which packs the
+//! bits of each word from its little endian memory, so they don't share the bit packing of
+//! simple_modulus.rs. Products of the test keys fit 32 bits, as with retail keys.
+
+extern crate mu_proto;
+
+use mu_proto::{PacketSerial, SimpleModulus, SimpleModulusError, SimpleModulusKeys};
+
+const KEY_FILE_XOR: [u32; 4] = [0x3F08_A79B, 0xE25C_C287, 0x93D2_7AB9, 0x20DE_A7BF];
+
+const MODULUS: [u32; 4] = [65599, 67891, 70001, 72227];
+const ENC_KEY: [u32; 4] = [56168, 48522, 29159, 30769];
+const DEC_KEY: [u32; 4] = [31544, 2047, 57011, 10183];
+const XOR_KEY: [u32; 4] = [0x1234, 0xBEEF, 0x0F0F, 0x7A5C];
+
+/// Key file the way the original tools write it: a 0x1112 header, the file size and the three
+/// keys, each one masked by the file xor key.
+fn key_file(key: [u32; 4]) -> Vec<u8> {
+    let mut buf = vec![0x12, 0x11, 0x00, 0x00, 0x38, 0x00, 0x00, 0x00];
+
+    for words in &[MODULUS, key, XOR_KEY] {
+        for (word, mask) in words.iter().zip(KEY_FILE_XOR.iter()) {
+            buf.extend_from_slice(&(word ^ mask).to_le_bytes());
+        }
+    }
+    buf
+}
+
+fn cipher() -> SimpleModulus {
+    SimpleModulus::new(
+        SimpleModulusKeys::parse(&key_file(ENC_KEY)).unwrap(),
+        SimpleModulusKeys::parse(&key_file(DEC_KEY)).unwrap(),
+    )
+}
+
+#[test]
+fn parses_key_files() {
+    let keys = SimpleModulusKeys::parse(&key_file(ENC_KEY)).unwrap();
+
+    assert_eq!(keys.modulus, MODULUS);
+    assert_eq!(keys.key, ENC_KEY);
+    assert_eq!(keys.xor, XOR_KEY);
+}
+
+#[test]
+fn rejects_invalid_key_files() {
+    let invalid = |buf: &[u8]| match SimpleModulusKeys::parse(buf) {
+        Err(SimpleModulusError::InvalidKeyFile) => (),
+        other => panic!("expected an invalid key file, got {:?}", other),
+    };
+
+    let mut header = key_file(ENC_KEY);
+    header[0] = 0x13;
+    invalid(&header);
+
+    let mut size = key_file(ENC_KEY);
+    size[4] = 0x36;
+    invalid(&size);
+    invalid(&key_file(ENC_KEY)[..50]);
+
+    //The first word of the modulus, stored masked.
+    let mut modulus = key_file(ENC_KEY);
+    modulus[8..12].copy_from_slice(&KEY_FILE_XOR[0].to_le_bytes());
+    invalid(&modulus);
+}
+
+#[test]
+fn missing_key_files_fail_to_load() {
+    assert!(SimpleModulusKeys::load("missing/Enc1.dat").is_err());
+}
+
+#[test]
+fn encrypts_known_frames() {
+    let cipher = cipher();
+
+    let plain = [0xC3, 0x06, 0xF1, 0x00, 0x01, 0x02];
+    let wire = [0xC3, 0x0D, 0xDB, 0xCC, 0x2C, 0xB0, 0x8B, 0x3A, 0x40, 0x3C, 0x1C, 0x35, 0x0D];
+
+    assert_eq!(cipher.encrypt_frame(&plain, 7).unwrap(), wire);
+    assert_eq!(cipher.decrypt_frame(&wire).unwrap(), (plain.to_vec(), 7));
+
+    #[rustfmt::skip]
+    let plain = [0xC4, 0x00, 0x0D, 0xF4, 0x06, 0x00, 0x01, 0x15, 0x00, 0x14, 0xFF, 0x99, 0x42];
+    #[rustfmt::skip]
+    let wire = [
+        0xC4, 0x00, 0x19,
+        0xA7, 0x29, 0x1B, 0x12, 0xC4, 0x56, 0xE1, 0x88, 0x19, 0x15, 0x20,
+        0xE3, 0xB8, 0x0A, 0xA0, 0xC0, 0xE5, 0x93, 0xE8, 0xFC, 0xE2, 0xDC,
+    ];
+
+    assert_eq!(cipher.encrypt_frame(&plain, 0x2A).unwrap(), wire);
+    assert_eq!(cipher.decrypt_frame(&wire).unwrap(), (plain.to_vec(), 0x2A));
+}
+
+#[test]
+fn frames_round_trip() {
+    let cipher = cipher();
+
+    for len in 0..40 {
+        let body: Vec<u8> = (0..len).map(|i| (i * 37 + len) as u8).collect();
+
+        let mut c3 = vec![0xC3, (body.len() + 3) as u8, 0xF3];
+        c3.extend_from_slice(&body);
+
+        let enc = cipher.encrypt_frame(&c3, len as u8).unwrap();
+        assert_eq!(enc[1] as usize, enc.len());
+        assert_eq!(cipher.decrypt_frame(&enc).unwrap(), (c3, len as u8));
+
+        let mut c4 = vec![0xC4, 0x00, (body.len() + 4) as u8, 0xF3];
+        c4.extend_from_slice(&body);
+
+        let enc = cipher.encrypt_frame(&c4, 0xFF).unwrap();
+        assert_eq!(usize::from(enc[1]) << 8 | usize::from(enc[2]), enc.len());
+        assert_eq!(cipher.decrypt_frame(&enc).unwrap(), (c4, 0xFF));
+    }
+}
+
+#[test]
+fn rejects_tampered_frames() {
+    let cipher = cipher();
+    let wire = cipher.encrypt_frame(&[0xC3, 0x06, 0xF1, 0x00, 0x01, 0x02], 7).unwrap();
+
+    let mut tampered = wire.clone();
+    *tampered.last_mut().unwrap() ^= 0x01;
+    match cipher.decrypt_frame(&tampered) {
+        Err(SimpleModulusError::InvalidChecksum) => (),
+        other => panic!("expected a checksum mismatch, got {:?}", other),
+    }
+
+    match cipher.decrypt_frame(&wire[..wire.len() - 1]) {
+        Err(SimpleModulusError::InvalidLength(10)) => (),
+        other => panic!("expected an invalid length, got {:?}", other),
+    }
+}
+
+#[test]
+fn serials_must_follow_each_other() {
+    let mut serial = PacketSerial::new();
+
+    serial.check(0).unwrap();
+    serial.check(1).unwrap();
+
+    match serial.check(5) {
+        Err(SimpleModulusError::InvalidSerial(2, 5)) => (),
+        other => panic!("expected a serial mismatch, got {:?}", other),
+    }
+
+    //A mismatch still counts the packet, as the peer did.
+    serial.check(3).unwrap();
+
+    let mut serial = PacketSerial::new();
+    for _ in 0..256 {
+        serial.take();
+    }
+    assert_eq!(serial.current(), 0);
+}
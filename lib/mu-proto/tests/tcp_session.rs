@@ -0,0 +1,143 @@
+//! Outbound buffering of `TcpSessionWriter` over a socket that only takes a few bytes at once.
+
+extern crate futures;
+extern crate mu_proto;
+extern crate tokio;
+
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures::SinkExt;
+use tokio::io::AsyncWrite;
+
+use mu_proto::{MuCodec, MuPacket, SessionOptions, TcpSessionWriter};
+
+/// Socket taking at most `chunk` bytes per write and refusing every third write, as a
+/// congested one would.
+struct Trickle {
+    chunk: usize,
+    calls: usize,
+    written: Arc<Mutex<Vec<u8>>>,
+    //Most frames handed to a single write.
+    max_slices: Arc<Mutex<usize>>,
+}
+
+impl AsyncWrite for Trickle {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.poll_write_vectored(cx, &[io::IoSlice::new(buf)])
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        bufs: &[io::IoSlice],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.calls += 1;
+
+        if this.calls.is_multiple_of(3) {
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+
+        let mut max_slices = this.max_slices.lock().unwrap();
+        *max_slices = (*max_slices).max(bufs.len());
+
+        let mut written = this.written.lock().unwrap();
+        let mut left = this.chunk;
+
+        for buf in bufs {
+            let n = buf.len().min(left);
+            written.extend_from_slice(&buf[..n]);
+            left -= n;
+
+            if left == 0 {
+                break;
+            }
+        }
+
+        Poll::Ready(Ok(this.chunk - left))
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        true
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn packet(i: u8) -> MuPacket {
+    let len = 4 + i as usize;
+    let mut frame = vec![0xC1, len as u8, 0xF3];
+    frame.extend((0..=i).map(|b| b.wrapping_mul(i)));
+    MuPacket::new(&frame).unwrap()
+}
+
+#[tokio::test]
+async fn short_writes_keep_frames_in_order() {
+    let written = Arc::new(Mutex::new(vec![]));
+    let max_slices = Arc::new(Mutex::new(0));
+
+    let io = Trickle {
+        chunk: 5,
+        calls: 0,
+        written: Arc::clone(&written),
+        max_slices: Arc::clone(&max_slices),
+    };
+    let codec = MuCodec::new(1, 1, None, &SessionOptions::default());
+    let mut writer = TcpSessionWriter::new(io, codec);
+
+    let mut expected = vec![];
+    for i in 0..20 {
+        let pkt = packet(i);
+        expected.extend_from_slice(pkt.frame());
+        writer.feed(pkt).await.unwrap();
+    }
+
+    //Nothing is written before a flush, so the frames go out together.
+    assert_eq!(writer.pending_len(), expected.len());
+    assert!(written.lock().unwrap().is_empty());
+
+    writer.flush().await.unwrap();
+
+    assert_eq!(writer.pending_len(), 0);
+    assert_eq!(*written.lock().unwrap(), expected);
+    assert!(*max_slices.lock().unwrap() > 1);
+}
+
+#[tokio::test]
+async fn frames_sent_while_others_are_pending_follow_them() {
+    let written = Arc::new(Mutex::new(vec![]));
+
+    let io = Trickle {
+        chunk: 3,
+        calls: 0,
+        written: Arc::clone(&written),
+        max_slices: Arc::new(Mutex::new(0)),
+    };
+    let codec = MuCodec::new(1, 1, None, &SessionOptions::default());
+    let mut writer = TcpSessionWriter::new(io, codec);
+
+    let mut expected = vec![];
+    for i in 0..10 {
+        let pkt = packet(i);
+        expected.extend_from_slice(pkt.frame());
+        writer.send(pkt).await.unwrap();
+        assert_eq!(writer.pending_len(), 0);
+    }
+
+    writer.close().await.unwrap();
+    assert_eq!(*written.lock().unwrap(), expected);
+}
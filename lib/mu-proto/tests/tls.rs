@@ -0,0 +1,119 @@
+//! Mutual TLS between a listener and a link, with the self-signed certificates of tests/certs.
+
+extern crate futures;
+extern crate mu_proto;
+extern crate tokio;
+
+use std::time::Duration;
+
+use futures::StreamExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+use mu_proto::{ConnectResult, Message, NetworkEvent, ReconnectPolicy, Server, SessionOptions,
+               TlsConfig};
+
+fn cert(name: &str) -> String {
+    format!("{}/tests/certs/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+fn tls(name: &str, ca: &str) -> TlsConfig {
+    TlsConfig::load(&cert(&format!("{}.pem", name)), &cert(&format!("{}.key", name)), &cert(ca))
+        .unwrap()
+}
+
+fn options(tls: TlsConfig) -> SessionOptions {
+    SessionOptions {
+        tls: Some(tls),
+        reconnect: ReconnectPolicy {
+            max_attempts: Some(1),
+            ..ReconnectPolicy::default()
+        },
+        ..SessionOptions::default()
+    }
+}
+
+fn listen(port: u16) -> Server {
+    let mut server = Server::new();
+    server.start_tcp("127.0.0.1", port, 1, options(tls("server", "ca.pem"))).unwrap();
+    server
+}
+
+/// Nothing gets past the handshake, so the listener never sees a session.
+async fn assert_no_session(server: &mut Server) {
+    if let Ok(evt) = timeout(Duration::from_millis(300), server.next()).await {
+        panic!("expected no session, got {:?}", evt);
+    }
+}
+
+#[tokio::test]
+async fn mutual_tls_link() {
+    let mut listener = listen(47141);
+
+    let mut link = Server::new();
+    link.connect_to("127.0.0.1", 47141, 2, options(tls("client", "ca.pem"))).unwrap();
+
+    let mut session = match link.next().await {
+        Some(NetworkEvent::ClientConnected(session)) => session,
+        other => panic!("expected a connection, got {:?}", other),
+    };
+
+    match listener.next().await {
+        Some(NetworkEvent::ClientConnected(_)) => (),
+        other => panic!("expected a connection, got {:?}", other),
+    }
+
+    session.send_msg(&ConnectResult { res: 1 }).unwrap();
+
+    match listener.next().await {
+        Some(NetworkEvent::ClientPacket((from, pkt))) => {
+            let msg = Message::decode(&pkt, from.profile).unwrap();
+            assert_eq!(msg, Message::ConnectResult(ConnectResult { res: 1 }));
+        }
+        other => panic!("expected a packet, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn rejects_clients_of_other_cas() {
+    let mut listener = listen(47142);
+
+    let mut link = Server::new();
+    link.connect_to("127.0.0.1", 47142, 2, options(tls("rogue", "ca.pem"))).unwrap();
+
+    assert_no_session(&mut listener).await;
+}
+
+#[tokio::test]
+async fn rejects_plain_clients() {
+    let mut listener = listen(47143);
+
+    let mut client = TcpStream::connect("127.0.0.1:47143").await.unwrap();
+    client.write_all(&[0xC1, 0x04, 0x00, 0x01]).await.unwrap();
+
+    assert_no_session(&mut listener).await;
+}
+
+#[tokio::test]
+async fn links_check_the_listener_certificate() {
+    let _listener = listen(47144);
+
+    //The listener certificate is for localhost, not for the name the link expects.
+    let tls = tls("client", "ca.pem").with_server_name("cs.example.com").unwrap();
+
+    let mut link = Server::new();
+    link.connect_to("127.0.0.1", 47144, 2, options(tls)).unwrap();
+
+    match link.next().await {
+        Some(NetworkEvent::LinkFailed((2, 1))) => (),
+        other => panic!("expected a failed link, got {:?}", other),
+    }
+}
+
+#[test]
+fn load_errors() {
+    assert!(TlsConfig::load(&cert("missing.pem"), &cert("client.key"), &cert("ca.pem")).is_err());
+    assert!(TlsConfig::load(&cert("client.pem"), &cert("ca.pem"), &cert("ca.pem")).is_err());
+    assert!(TlsConfig::load(&cert("client.pem"), &cert("rogue.key"), &cert("ca.pem")).is_err());
+}
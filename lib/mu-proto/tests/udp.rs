@@ -0,0 +1,112 @@
+//! Datagrams of sockets bound by `start_udp`.
+
+extern crate futures;
+extern crate mu_proto;
+extern crate tokio;
+
+use std::net::UdpSocket;
+use std::time::Duration;
+
+use futures::StreamExt;
+use tokio::time::timeout;
+
+use mu_proto::{ConnectResult, IpFilter, IpRules, Message, NetworkEvent, ProtoProfile, Protocol,
+               Server, ServerInfo, SessionOptions};
+
+fn server_info() -> ServerInfo {
+    ServerInfo {
+        svr_code: 3,
+        ip: [0; 16],
+        port: 55590,
+        perc: 10,
+        usr_cnt: 10,
+        acc_cnt: 0,
+        mx_usr_cnt: 100,
+    }
+}
+
+async fn next_datagram(server: &mut Server) -> Message {
+    match server.next().await {
+        Some(NetworkEvent::Datagram((socket, _, pkt))) => {
+            Message::decode(&pkt, socket.profile).unwrap()
+        }
+        other => panic!("expected a datagram, got {:?}", other),
+    }
+}
+
+async fn assert_no_datagram(server: &mut Server) {
+    if let Ok(evt) = timeout(Duration::from_millis(300), server.next()).await {
+        panic!("expected no datagram, got {:?}", evt);
+    }
+}
+
+#[tokio::test]
+async fn datagrams_carry_the_sender() {
+    let mut cs = Server::new();
+    cs.start_udp("127.0.0.1", 47151, 2, SessionOptions::default()).unwrap();
+
+    let mut gs = Server::new();
+    let status = gs.start_udp("127.0.0.1", 0, 2, SessionOptions::default()).unwrap();
+    status.send_msg_to(&server_info(), "127.0.0.1:47151".parse().unwrap()).unwrap();
+
+    let (socket, from) = match cs.next().await {
+        Some(NetworkEvent::Datagram((socket, from, pkt))) => {
+            let msg = Message::decode(&pkt, socket.profile).unwrap();
+            assert_eq!(msg, Message::ServerInfo(server_info()));
+            (socket, from)
+        }
+        other => panic!("expected a datagram, got {:?}", other),
+    };
+
+    assert_eq!(socket.kind, 2);
+    assert_eq!(from, status.local_addr().unwrap());
+
+    //The socket of the event answers from the address the sender wrote to.
+    socket.send_msg_to(&ConnectResult { res: 1 }, from).unwrap();
+    assert_eq!(next_datagram(&mut gs).await, Message::ConnectResult(ConnectResult { res: 1 }));
+}
+
+#[tokio::test]
+async fn datagrams_hold_whole_packets() {
+    let mut cs = Server::new();
+    cs.start_udp("127.0.0.1", 47152, 2, SessionOptions::default()).unwrap();
+
+    let profile = ProtoProfile::default();
+    let info = server_info().to_packet(profile).unwrap();
+    let res = ConnectResult { res: 1 }.to_packet(profile).unwrap();
+
+    let mut both = info.frame().to_vec();
+    both.extend_from_slice(res.frame());
+
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client.send_to(&both, "127.0.0.1:47152").unwrap();
+
+    assert_eq!(next_datagram(&mut cs).await, Message::ServerInfo(server_info()));
+    assert_eq!(next_datagram(&mut cs).await, Message::ConnectResult(ConnectResult { res: 1 }));
+
+    //A packet cut short is dropped along with the rest of its datagram.
+    client.send_to(&both[..both.len() - 1], "127.0.0.1:47152").unwrap();
+    assert_no_datagram(&mut cs).await;
+}
+
+#[tokio::test]
+async fn filters_drop_datagrams() {
+    let filter = IpFilter::new(IpRules {
+        allow: vec![],
+        deny: vec!["127.0.0.0/8".parse().unwrap()],
+    });
+
+    let opts = SessionOptions {
+        filter: Some(filter),
+        ..SessionOptions::default()
+    };
+
+    let mut cs = Server::new();
+    cs.start_udp("127.0.0.1", 47153, 2, opts).unwrap();
+
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let info = server_info().to_packet(ProtoProfile::default()).unwrap();
+    client.send_to(info.frame(), "127.0.0.1:47153").unwrap();
+
+    assert_no_datagram(&mut cs).await;
+}
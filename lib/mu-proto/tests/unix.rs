@@ -0,0 +1,103 @@
+//! Listeners and links over Unix sockets, for servers on the same host.
+#![cfg(unix)]
+
+extern crate futures;
+extern crate mu_proto;
+extern crate tokio;
+
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::{fs, process};
+
+use futures::StreamExt;
+
+use mu_proto::{ConnectResult, Endpoint, Message, NetworkEvent, ReconnectPolicy, Server,
+               SessionOptions};
+
+fn socket(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("mu-proto-{}-{}.sock", name, process::id()));
+    fs::remove_file(&path).ok();
+    path
+}
+
+fn options() -> SessionOptions {
+    SessionOptions {
+        reconnect: ReconnectPolicy {
+            initial_delay: Duration::from_millis(10),
+            max_attempts: Some(3),
+            ..ReconnectPolicy::default()
+        },
+        ..SessionOptions::default()
+    }
+}
+
+#[test]
+fn endpoints_parse_and_display() {
+    let tcp: Endpoint = "127.0.0.1:55557".parse().unwrap();
+    assert_eq!(tcp, Endpoint::Tcp("127.0.0.1:55557".parse().unwrap()));
+    assert_eq!(tcp.to_string(), "127.0.0.1:55557");
+
+    let unix: Endpoint = "unix:/run/lcemu/cs.sock".parse().unwrap();
+    assert_eq!(unix, Endpoint::Unix(PathBuf::from("/run/lcemu/cs.sock")));
+    assert_eq!(unix.to_string(), "unix:/run/lcemu/cs.sock");
+
+    assert!("unix:".parse::<Endpoint>().is_err());
+    assert!("127.0.0.1".parse::<Endpoint>().is_err());
+    assert!("cs.sock".parse::<Endpoint>().is_err());
+}
+
+#[tokio::test]
+async fn unix_link() {
+    let path = socket("link");
+
+    let mut listener = Server::new();
+    listener.start_unix(path.to_str().unwrap(), 1, options()).unwrap();
+
+    let mut link = Server::new();
+    link.connect_to_any(vec![Endpoint::Unix(path.clone())], 2, options()).unwrap();
+
+    let mut session = match link.next().await {
+        Some(NetworkEvent::ClientConnected(session)) => session,
+        other => panic!("expected a connection, got {:?}", other),
+    };
+
+    match listener.next().await {
+        Some(NetworkEvent::ClientConnected(session)) => assert_eq!(session.kind, 1),
+        other => panic!("expected a connection, got {:?}", other),
+    }
+
+    session.send_msg(&ConnectResult { res: 1 }).unwrap();
+
+    match listener.next().await {
+        Some(NetworkEvent::ClientPacket((from, pkt))) => {
+            let msg = Message::decode(&pkt, from.profile).unwrap();
+            assert_eq!(msg, Message::ConnectResult(ConnectResult { res: 1 }));
+        }
+        other => panic!("expected a packet, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn replaces_stale_sockets() {
+    let path = socket("stale");
+
+    //A socket file nothing listens on anymore, as left by a crash.
+    drop(UnixListener::bind(&path).unwrap());
+    assert!(path.exists());
+
+    let mut listener = Server::new();
+    listener.start_unix(path.to_str().unwrap(), 1, options()).unwrap();
+
+    let mut link = Server::new();
+    link.connect_to_any(vec![Endpoint::Unix(path.clone())], 2, options()).unwrap();
+
+    match listener.next().await {
+        Some(NetworkEvent::ClientConnected(_)) => (),
+        other => panic!("expected a connection, got {:?}", other),
+    }
+
+    //But a socket still in use is left alone.
+    let mut other = Server::new();
+    assert!(other.start_unix(path.to_str().unwrap(), 1, options()).is_err());
+}
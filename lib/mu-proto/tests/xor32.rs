@@ -0,0 +1,109 @@
+//! XOR32 obfuscation of client packets, alone and through `MuCodec`.
+//!
+//! The known-answer frames were obfuscated by a port of the original client loop, kept apart
+//! from xor32.rs.
+
+extern crate bytes;
+extern crate mu_proto;
+extern crate tokio_util;
+
+use bytes::BytesMut;
+use tokio_util::codec::Decoder;
+
+use mu_proto::{xor32, MuCodec, MuPacket, SessionOptions};
+
+const C1_PLAIN: [u8; 10] = [0xC1, 0x0A, 0xF1, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07];
+const C1_WIRE: [u8; 10] = [0xC1, 0x0A, 0xF1, 0x79, 0xC7, 0x76, 0xED, 0x9B, 0xBE, 0x11];
+
+//Longer than the key, so it wraps around.
+fn c2_plain() -> Vec<u8> {
+    let mut frame = vec![0xC2, 0x00, 0x26, 0xF4, 0x06];
+    frame.extend(0..33);
+    frame
+}
+
+#[rustfmt::skip]
+const C2_WIRE: [u8; 38] = [
+    0xC2, 0x00, 0x26, 0xF4,
+    0x4E, 0xFC, 0x62, 0x13, 0x33, 0x9F, 0x64, 0xD4, 0x9A, 0xCF, 0xFF, 0xA8, 0x29, 0xEE, 0x80,
+    0x03, 0xE6, 0x8B, 0xB1, 0xFC, 0x2C, 0x89, 0x75, 0xE0, 0xDE, 0x97, 0x66, 0x2A, 0xD6, 0xA7,
+    0x80, 0x17, 0xB4, 0x26,
+];
+
+#[test]
+fn encodes_known_frames() {
+    let mut frame = C1_PLAIN;
+    xor32::encode(&mut frame);
+    assert_eq!(frame, C1_WIRE);
+
+    let mut frame = c2_plain();
+    xor32::encode(&mut frame);
+    assert_eq!(frame, C2_WIRE);
+}
+
+#[test]
+fn decodes_known_frames() {
+    let mut frame = C1_WIRE;
+    xor32::decode(&mut frame);
+    assert_eq!(frame, C1_PLAIN);
+
+    let mut frame = C2_WIRE;
+    xor32::decode(&mut frame);
+    assert_eq!(&frame[..], &c2_plain()[..]);
+}
+
+#[test]
+fn frames_round_trip() {
+    for len in 0..70 {
+        let mut plain = vec![0xC2, 0x00, (len + 4) as u8, 0xF3];
+        plain.extend((0..len).map(|i| (i * 7) as u8));
+
+        let mut frame = plain.clone();
+        xor32::encode(&mut frame);
+        xor32::decode(&mut frame);
+        assert_eq!(frame, plain);
+    }
+
+    //Frames too short to have a body are left alone.
+    let mut frame = [0xC1, 0x03, 0xF1];
+    xor32::encode(&mut frame);
+    assert_eq!(frame, [0xC1, 0x03, 0xF1]);
+}
+
+#[test]
+fn codec_applies_xor32_only_when_enabled() {
+    let pkt = MuPacket::new(&C1_PLAIN).unwrap();
+
+    let mut plain = MuCodec::new(1, 1, None, &SessionOptions::default());
+    assert_eq!(&plain.encode_frame(&pkt).unwrap()[..], &C1_PLAIN);
+
+    let mut buf = BytesMut::from(&C1_PLAIN[..]);
+    assert_eq!(&plain.decode(&mut buf).unwrap().unwrap().frame()[..], &C1_PLAIN);
+
+    let opts = SessionOptions {
+        xor32: true,
+        ..SessionOptions::default()
+    };
+    let mut xor = MuCodec::new(1, 1, None, &opts);
+    assert_eq!(&xor.encode_frame(&pkt).unwrap()[..], &C1_WIRE);
+
+    let mut buf = BytesMut::from(&C2_WIRE[..]);
+    assert_eq!(&xor.decode(&mut buf).unwrap().unwrap().frame()[..], &c2_plain()[..]);
+}
+
+#[test]
+fn xor32_frames_are_shared_by_sessions() {
+    let opts = SessionOptions {
+        xor32: true,
+        ..SessionOptions::default()
+    };
+    let pkt = MuPacket::new(&C1_PLAIN).unwrap();
+
+    //Each session encodes its own clone, as a broadcast does.
+    let first = MuCodec::new(1, 1, None, &opts).encode_frame(&pkt.clone()).unwrap();
+    let second = MuCodec::new(2, 1, None, &opts).encode_frame(&pkt.clone()).unwrap();
+
+    assert_eq!(&first[..], &C1_WIRE);
+    assert_eq!(first.as_ptr(), second.as_ptr());
+    assert_eq!(&pkt.frame()[..], &C1_PLAIN);
+}
@@ -1,3 +1,10 @@
+extern crate config;
+extern crate failure;
+extern crate mu_proto;
+extern crate tokio;
+
+pub mod settings;
+
 pub fn get_u32(buf: &[u8]) -> u32 {
     (buf[0] as u32) << 24 | (buf[1] as u32) << 16 | (buf[2] as u32) << 8 | buf[3] as u32
 }
@@ -0,0 +1,236 @@
+//! Reads the `[network]` and `[capture]` settings shared by the servers into mu-proto options.
+//! Listeners and links are configured by a key prefix, like `external` or `cs`, so the same
+//! settings read `external_queue_len` on one listener and `cs_queue_len` on a link.
+
+use std::fs;
+use std::time::{Duration, SystemTime};
+
+use config::{Config, ConfigError, File};
+use failure::Error;
+use mu_proto::prelude::*;
+
+/// How often the config file is checked for filter changes.
+const FILTER_RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+pub fn load(path: &str) -> Result<Config, ConfigError> {
+    Config::builder().add_source(File::with_name(path)).build()
+}
+
+/// Reads the client version of a listener from `<prefix>_profile`.
+pub fn setup_profile(settings: &Config, prefix: &str) -> ProtoProfile {
+    match settings.get_string(&format!("network.{}_profile", prefix)) {
+        Ok(name) => name.parse::<ProtoProfile>().unwrap_or_else(|e| {
+            println!("{} Using {}.", e, ProtoProfile::default());
+            ProtoProfile::default()
+        }),
+        Err(_) => ProtoProfile::default(),
+    }
+}
+
+/// Reads the session settings of a listener or link: `<prefix>_queue_len`, `<prefix>_overflow`,
+/// `<prefix>_idle_ms`, `<prefix>_heartbeat_ms` and the connection limits.
+///
+/// Server handlers send from sync code, where `OverflowPolicy::Block` can't wait for room, so
+/// `block:<ms>` is refused here. It is only meant for senders using `SessionRef::send_async`.
+pub fn setup_session(settings: &Config, prefix: &str, opts: &mut SessionOptions) {
+    if let Ok(len) = settings.get_int(&format!("network.{}_queue_len", prefix)) {
+        opts.queue_len = len as usize;
+    }
+
+    if let Ok(name) = settings.get_string(&format!("network.{}_overflow", prefix)) {
+        match name.parse::<OverflowPolicy>() {
+            Ok(OverflowPolicy::Block(_)) => println!(
+                "Overflow policy {} needs async senders, the server handlers aren't. Using {}.",
+                name, opts.overflow
+            ),
+            Ok(policy) => opts.overflow = policy,
+            Err(e) => println!("{} Using {}.", e, opts.overflow),
+        }
+    }
+
+    if let Ok(ms) = settings.get_int(&format!("network.{}_idle_ms", prefix)) {
+        opts.idle_timeout = Some(Duration::from_millis(ms as u64));
+    }
+
+    if let Ok(ms) = settings.get_int(&format!("network.{}_heartbeat_ms", prefix)) {
+        opts.heartbeat = Some(Duration::from_millis(ms as u64));
+    }
+
+    setup_limits(settings, prefix, &mut opts.limits);
+}
+
+/// Reads `<prefix>_max_connections`, `<prefix>_max_per_ip`, `<prefix>_max_packets_per_sec`,
+/// `<prefix>_max_bytes_per_sec` and `<prefix>_ban_ms`.
+pub fn setup_limits(settings: &Config, prefix: &str, limits: &mut ConnectionLimits) {
+    let get = |key: &str| settings.get_int(&format!("network.{}_{}", prefix, key)).ok();
+
+    if let Some(max) = get("max_connections") {
+        limits.max_connections = Some(max as usize);
+    }
+
+    if let Some(max) = get("max_per_ip") {
+        limits.max_per_ip = Some(max as usize);
+    }
+
+    if let Some(max) = get("max_packets_per_sec") {
+        limits.max_packets_per_sec = Some(max as u32);
+    }
+
+    if let Some(max) = get("max_bytes_per_sec") {
+        limits.max_bytes_per_sec = Some(max as u32);
+    }
+
+    if let Some(ms) = get("ban_ms") {
+        limits.ban_time = Some(Duration::from_millis(ms as u64));
+    }
+}
+
+/// Reads `<prefix>_reconnect_ms`, `<prefix>_reconnect_max_ms`, `<prefix>_reconnect_jitter` and
+/// `<prefix>_reconnect_attempts` of a link.
+pub fn setup_reconnect(settings: &Config, prefix: &str, policy: &mut ReconnectPolicy) {
+    let key = |name: &str| format!("network.{}_reconnect_{}", prefix, name);
+
+    if let Ok(ms) = settings.get_int(&key("ms")) {
+        policy.initial_delay = Duration::from_millis(ms as u64);
+    }
+
+    if let Ok(ms) = settings.get_int(&key("max_ms")) {
+        policy.max_delay = Duration::from_millis(ms as u64);
+    }
+
+    if let Ok(jitter) = settings.get_float(&key("jitter")) {
+        policy.jitter = jitter;
+    }
+
+    if let Ok(attempts) = settings.get_int(&key("attempts")) {
+        policy.max_attempts = Some(attempts as u32);
+    }
+}
+
+/// Reads `<prefix>_tls_cert`, `<prefix>_tls_key` and `<prefix>_tls_ca`, and for links the
+/// optional `<prefix>_tls_name` the listener certificate must have. TLS is off without a cert.
+pub fn setup_tls(settings: &Config, prefix: &str) -> Result<Option<TlsConfig>, Error> {
+    let get = |key: &str| settings.get_string(&format!("network.{}_tls_{}", prefix, key));
+
+    let cert = match get("cert") {
+        Ok(cert) => cert,
+        Err(_) => return Ok(None),
+    };
+
+    let tls = TlsConfig::load(&cert, &get("key")?, &get("ca")?)?;
+
+    match get("name") {
+        Ok(name) => Ok(Some(tls.with_server_name(&name)?)),
+        Err(_) => Ok(Some(tls)),
+    }
+}
+
+/// Opens the packet capture file, when `capture.file` is set.
+pub fn setup_capture(settings: &Config) -> Option<Capture> {
+    let path = settings.get_string("capture.file").ok()?;
+
+    match Capture::create(&path) {
+        Ok(capture) => {
+            println!("Capturing packets to {}", path);
+            Some(capture)
+        }
+        Err(e) => {
+            println!("Failed to create capture file {}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// Address filters of the listeners of a server, reloaded when its config file changes.
+pub struct Filters {
+    path: &'static str,
+    filters: Vec<(&'static str, IpFilter)>,
+}
+
+impl Filters {
+    pub fn new(path: &'static str) -> Filters {
+        Filters {
+            path,
+            filters: vec![],
+        }
+    }
+
+    /// Reads the `<prefix>_allow` and `<prefix>_deny` CIDR lists of a listener.
+    pub fn setup(&mut self, settings: &Config, prefix: &'static str) -> Option<IpFilter> {
+        //A broken list must not open the listener to everyone.
+        let rules = read_rules(settings, prefix).unwrap_or_else(|e| {
+            println!("Invalid {} filter, refusing every connection: {}", prefix, e);
+            IpRules {
+                allow: vec![],
+                deny: vec!["0.0.0.0/0".parse().unwrap(), "::/0".parse().unwrap()],
+            }
+        });
+
+        let filter = IpFilter::new(rules);
+        self.filters.push((prefix, filter.clone()));
+        Some(filter)
+    }
+
+    /// Reloads the filters whenever the config file changes. Must be called from within the
+    /// tokio runtime.
+    pub fn watch(self) {
+        tokio::spawn(async move {
+            let mut modified = modified_at(self.path);
+            let mut ticks = tokio::time::interval(FILTER_RELOAD_INTERVAL);
+
+            loop {
+                ticks.tick().await;
+
+                let now = modified_at(self.path);
+                if now == modified {
+                    continue;
+                }
+                modified = now;
+
+                match load(self.path) {
+                    Ok(settings) => self.reload(&settings),
+                    Err(e) => println!("Failed to reload config file: {}", e),
+                }
+            }
+        });
+    }
+
+    fn reload(&self, settings: &Config) {
+        for (prefix, filter) in self.filters.iter() {
+            match read_rules(settings, prefix) {
+                Ok(rules) => {
+                    if rules != filter.rules() {
+                        println!("Reloaded {} filter.", prefix);
+                        filter.update(rules);
+                    }
+                }
+                Err(e) => println!("Invalid {} filter, keeping the current one: {}", prefix, e),
+            }
+        }
+    }
+}
+
+fn read_rules(settings: &Config, prefix: &str) -> Result<IpRules, Error> {
+    Ok(IpRules {
+        allow: read_nets(settings, &format!("network.{}_allow", prefix))?,
+        deny: read_nets(settings, &format!("network.{}_deny", prefix))?,
+    })
+}
+
+fn read_nets(settings: &Config, key: &str) -> Result<Vec<IpNet>, Error> {
+    let entries = match settings.get::<Vec<String>>(key) {
+        Ok(entries) => entries,
+        Err(ConfigError::NotFound(_)) => return Ok(vec![]),
+        Err(e) => return Err(e)?,
+    };
+
+    let mut nets = vec![];
+    for entry in entries.iter() {
+        nets.push(entry.parse()?);
+    }
+    Ok(nets)
+}
+
+fn modified_at(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
@@ -0,0 +1,124 @@
+//! Server settings read into mu-proto options.
+
+extern crate config;
+extern crate mu_proto;
+extern crate util;
+
+use std::time::Duration;
+
+use config::{Config, File, FileFormat};
+
+use mu_proto::prelude::*;
+use util::settings::{self, Filters};
+
+fn parse(toml: &str) -> Config {
+    Config::builder().add_source(File::from_str(toml, FileFormat::Toml)).build().unwrap()
+}
+
+#[test]
+fn sessions_read_their_prefix() {
+    let cfg = parse(
+        r#"
+        [network]
+        external_queue_len = 50
+        external_overflow = "drop_oldest"
+        external_idle_ms = 1000
+        external_max_per_ip = 3
+        external_ban_ms = 2000
+        external_profile = "1.04d"
+        internal_queue_len = 900
+        "#,
+    );
+
+    let mut opts = SessionOptions::default();
+    settings::setup_session(&cfg, "external", &mut opts);
+
+    assert_eq!(opts.queue_len, 50);
+    assert_eq!(opts.overflow, OverflowPolicy::DropOldest);
+    assert_eq!(opts.idle_timeout, Some(Duration::from_millis(1000)));
+    assert_eq!(opts.heartbeat, None);
+    assert_eq!(opts.limits.max_per_ip, Some(3));
+    assert_eq!(opts.limits.ban_time, Some(Duration::from_millis(2000)));
+
+    assert_eq!(settings::setup_profile(&cfg, "external"), ProtoProfile::V104d);
+    assert_eq!(settings::setup_profile(&cfg, "internal"), ProtoProfile::default());
+}
+
+#[test]
+fn invalid_values_keep_the_defaults() {
+    let cfg = parse(
+        r#"
+        [network]
+        listen_overflow = "sometimes"
+        listen_profile = "season 99"
+        link_overflow = "block:500"
+        "#,
+    );
+
+    let mut opts = SessionOptions::default();
+    settings::setup_session(&cfg, "listen", &mut opts);
+
+    assert_eq!(opts.overflow, OverflowPolicy::default());
+    assert_eq!(settings::setup_profile(&cfg, "listen"), ProtoProfile::default());
+
+    //Handlers send synchronously, so they could never wait for room.
+    settings::setup_session(&cfg, "link", &mut opts);
+    assert_eq!(opts.overflow, OverflowPolicy::default());
+}
+
+#[test]
+fn links_read_their_reconnect_policy() {
+    let cfg = parse(
+        r#"
+        [network]
+        cs_reconnect_ms = 200
+        cs_reconnect_max_ms = 5000
+        cs_reconnect_jitter = 0.5
+        cs_reconnect_attempts = 4
+        "#,
+    );
+
+    let mut policy = ReconnectPolicy::default();
+    settings::setup_reconnect(&cfg, "cs", &mut policy);
+
+    assert_eq!(
+        policy,
+        ReconnectPolicy {
+            initial_delay: Duration::from_millis(200),
+            max_delay: Duration::from_millis(5000),
+            jitter: 0.5,
+            max_attempts: Some(4),
+        }
+    );
+}
+
+#[test]
+fn tls_is_off_without_a_cert() {
+    let cfg = parse("[network]\n");
+    assert!(settings::setup_tls(&cfg, "cs").unwrap().is_none());
+
+    //A cert without the rest of the setup is an error, never plain text.
+    let cfg = parse("[network]\ncs_tls_cert = \"gs.pem\"\n");
+    assert!(settings::setup_tls(&cfg, "cs").is_err());
+}
+
+#[test]
+fn filters_refuse_everyone_on_broken_lists() {
+    let cfg = parse(
+        r#"
+        [network]
+        internal_allow = ["10.0.0.0/8"]
+        external_deny = ["not a cidr"]
+        "#,
+    );
+
+    let mut filters = Filters::new("missing.toml");
+
+    let internal = filters.setup(&cfg, "internal").unwrap();
+    assert!(internal.allows("10.1.2.3".parse().unwrap()));
+    assert!(!internal.allows("192.168.0.1".parse().unwrap()));
+
+    let external = filters.setup(&cfg, "external").unwrap();
+    assert!(!external.allows("8.8.8.8".parse().unwrap()));
+    assert!(!external.allows("::1".parse().unwrap()));
+}